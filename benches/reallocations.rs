@@ -0,0 +1,81 @@
+//! Counts the allocations and reallocations made while building a path, with the plain `/` chain that
+//! `path!` used to expand to, and with `path!` itself, which reserves the whole length up front.
+//!
+//! Run with `cargo bench --bench reallocations`.
+
+use path_dsl::{path, CopylessDSL, PathDSL};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static REALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        REALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn measure<T>(name: &str, input: impl Fn() -> T, build: impl Fn(T) -> PathBuf) {
+    // The input is made before counting starts, so only the path's own buffer is counted.
+    let input = black_box(input());
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let reallocations = REALLOCATIONS.load(Ordering::Relaxed);
+    let path = black_box(build(input));
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    let reallocations = REALLOCATIONS.load(Ordering::Relaxed) - reallocations;
+    println!(
+        "{:<32} {} bytes, {} allocations, {} reallocations",
+        name,
+        path.as_os_str().len(),
+        allocations,
+        reallocations
+    );
+}
+
+fn main() {
+    let segments: Vec<String> = (0..8).map(|i| format!("directory_{}", i)).collect();
+    let [a, b, c, d, e, f, g, h] = [
+        &segments[0],
+        &segments[1],
+        &segments[2],
+        &segments[3],
+        &segments[4],
+        &segments[5],
+        &segments[6],
+        &segments[7],
+    ];
+    let home = || String::from("/home/username");
+
+    measure("chain, borrowed first segment", home, |home| {
+        let path: PathDSL = CopylessDSL::new() / &home / a / b / c / d / e / f / g / h / "file.txt";
+        path.into()
+    });
+    measure("path!, borrowed first segment", home, |home| {
+        path!(&home | a | b | c | d | e | f | g | h | "file.txt")
+    });
+    measure("chain, owned first segment", home, |home| {
+        let path: PathDSL = CopylessDSL::new() / home / a / b / c / d / e / f / g / h / "file.txt";
+        path.into()
+    });
+    measure("path!, owned first segment", home, |home| {
+        path!(home | a | b | c | d | e | f | g | h | "file.txt")
+    });
+}
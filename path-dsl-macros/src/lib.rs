@@ -0,0 +1,331 @@
+//! Procedural `/` syntax for [`path-dsl`](https://docs.rs/path-dsl/).
+//!
+//! This crate is an implementation detail of `path-dsl`'s `proc-macro` feature and should be used through
+//! `path_dsl::slash::path!`, which is documented there. That wrapper passes its `$crate` before a `;`,
+//! and the expansion refers to `path-dsl` through it, so it works however the crate is named.
+//!
+//! Segments are split on every `/` outside of brackets, so `/` can't be used as division in a segment
+//! without wrapping it in parentheses. Runs of string literals are folded into a single
+//! `concat!` with the separator of the target, and every other segment goes through the same `Div`
+//! operations `path!` uses, so the two expand to the same code.
+
+#![deny(future_incompatible)]
+#![deny(rust_2018_idioms)]
+#![deny(unsafe_code)]
+#![warn(missing_docs)]
+#![warn(unused)]
+
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+use std::iter::FromIterator;
+
+/// Builds a path from segments separated by `/`, after the path of `path-dsl` and a `;`. See
+/// `path_dsl::slash::path!`.
+#[proc_macro]
+pub fn path(input: TokenStream) -> TokenStream {
+    match expand(input) {
+        Ok(tokens) => tokens,
+        Err(error) => error.into_compile_error(),
+    }
+}
+
+/// Error pointing at the tokens that caused it.
+struct Error {
+    span: Span,
+    message: &'static str,
+}
+
+impl Error {
+    fn into_compile_error(self) -> TokenStream {
+        let message = TokenTree::from(Literal::string(self.message));
+        let tokens = vec![
+            TokenTree::from(Ident::new("compile_error", self.span)),
+            TokenTree::from(Punct::new('!', Spacing::Alone)),
+            TokenTree::from(Group::new(Delimiter::Brace, TokenStream::from(message))),
+        ];
+        respan(TokenStream::from_iter(tokens), self.span)
+    }
+}
+
+/// A single segment between `/`s.
+enum Segment {
+    /// The root of the filesystem, from a leading `/`.
+    Root,
+    /// A string literal, which is checked at compile time unless it is `raw`.
+    Literal { literal: Literal, checked: bool },
+    /// Any other expression.
+    Expr(Vec<TokenTree>),
+}
+
+/// Run of segments which are all literals, and so can be folded into one.
+struct Run {
+    root: bool,
+    literals: Vec<(Literal, bool)>,
+}
+
+fn expand(input: TokenStream) -> Result<TokenStream, Error> {
+    let mut tokens: Vec<TokenTree> = input.into_iter().collect();
+    let krate = split_crate(&mut tokens)?;
+    let output = split_output(&mut tokens)?;
+    let segments = split_segments(tokens)?;
+
+    let mut chain = krate.path("::CopylessDSL::new()");
+    let mut run: Option<Run> = None;
+    for segment in segments {
+        match segment {
+            Segment::Root => {
+                run = Some(Run {
+                    root: true,
+                    literals: Vec::new(),
+                })
+            }
+            Segment::Literal { literal, checked } => {
+                run.get_or_insert_with(|| Run {
+                    root: false,
+                    literals: Vec::new(),
+                })
+                .literals
+                .push((literal, checked));
+            }
+            Segment::Expr(expr) => {
+                if let Some(run) = run.take() {
+                    push_segment(&krate, &mut chain, fold_run(&krate, run));
+                }
+                let span = expr[0].span();
+                // An invisible group keeps the expression together, like an `expr` fragment does.
+                push_segment(&krate, &mut chain, group(Delimiter::None, TokenStream::from_iter(expr), span));
+            }
+        }
+    }
+    if let Some(run) = run {
+        push_segment(&krate, &mut chain, fold_run(&krate, run));
+    }
+
+    let path = match output {
+        Some(ty) => {
+            let mut dsl = parse("::std::convert::Into::<");
+            dsl.extend(krate.path("::PathDSL"));
+            dsl.extend(parse(">::into"));
+            dsl.extend(group_tokens(Delimiter::Parenthesis, chain));
+            let mut converted = parse("::std::convert::Into::<");
+            converted.extend(ty);
+            converted.extend(parse(">::into"));
+            converted.extend(group_tokens(Delimiter::Parenthesis, dsl));
+            let mut output = krate.path("::Output");
+            output.extend(group_tokens(Delimiter::Parenthesis, converted));
+            output
+        }
+        None => chain,
+    };
+    let mut expanded = krate.path("::IntoOutput::into_output");
+    expanded.extend(group_tokens(Delimiter::Parenthesis, path));
+    Ok(expanded)
+}
+
+/// Removes the leading path of `path-dsl` and its `;` from `tokens`.
+fn split_crate(tokens: &mut Vec<TokenTree>) -> Result<Crate, Error> {
+    let semi = tokens.iter().position(|token| match token {
+        TokenTree::Punct(punct) => punct.as_char() == ';',
+        _ => false,
+    });
+    match semi {
+        Some(semi) if semi != 0 => {
+            let rest = tokens.split_off(semi + 1);
+            tokens.truncate(semi);
+            Ok(Crate(TokenStream::from_iter(std::mem::replace(tokens, rest))))
+        }
+        _ => Err(Error {
+            span: Span::call_site(),
+            message: "use `path_dsl::slash::path!` instead of `path_dsl_macros::path!`",
+        }),
+    }
+}
+
+/// Removes a trailing `=> Type` from `tokens`, and returns the type.
+fn split_output(tokens: &mut Vec<TokenTree>) -> Result<Option<Vec<TokenTree>>, Error> {
+    let arrow = tokens.windows(2).position(|pair| match pair {
+        [TokenTree::Punct(eq), TokenTree::Punct(gt)] => {
+            eq.as_char() == '=' && eq.spacing() == Spacing::Joint && gt.as_char() == '>'
+        }
+        _ => false,
+    });
+    let arrow = match arrow {
+        Some(arrow) => arrow,
+        None => return Ok(None),
+    };
+
+    let ty = tokens.split_off(arrow + 2);
+    if ty.is_empty() {
+        return Err(Error {
+            span: tokens[arrow + 1].span(),
+            message: "expected an output type after `=>`",
+        });
+    }
+    tokens.truncate(arrow);
+    Ok(Some(ty))
+}
+
+/// Splits `tokens` on every `/` outside of brackets.
+fn split_segments(tokens: Vec<TokenTree>) -> Result<Vec<Segment>, Error> {
+    let mut segments = Vec::new();
+    if tokens.is_empty() {
+        return Ok(segments);
+    }
+
+    let mut current = Vec::new();
+    let mut slash: Option<Span> = None;
+    for token in tokens {
+        match &token {
+            TokenTree::Punct(punct) if punct.as_char() == '/' => {
+                match (slash, current.is_empty()) {
+                    // A leading `/` is the root.
+                    (None, true) => segments.push(Segment::Root),
+                    (_, false) => segments.push(classify(std::mem::take(&mut current))),
+                    (Some(_), true) => {
+                        return Err(Error {
+                            span: punct.span(),
+                            message: "expected a path segment between `/`s",
+                        })
+                    }
+                }
+                slash = Some(punct.span());
+            }
+            _ => current.push(token),
+        }
+    }
+    match (slash, current.is_empty()) {
+        (_, false) => segments.push(classify(current)),
+        // A lone `/` is just the root.
+        (Some(_), true) if segments.len() == 1 => {}
+        (Some(span), true) => {
+            return Err(Error {
+                span,
+                message: "expected a path segment after `/`",
+            })
+        }
+        (None, true) => unreachable!("tokens are not empty"),
+    }
+    Ok(segments)
+}
+
+fn classify(mut tokens: Vec<TokenTree>) -> Segment {
+    match tokens.as_slice() {
+        [TokenTree::Literal(literal)] if is_string(literal) => Segment::Literal {
+            literal: literal.clone(),
+            checked: true,
+        },
+        [TokenTree::Ident(raw), TokenTree::Literal(literal)] if raw.to_string() == "raw" && is_string(literal) => {
+            Segment::Literal {
+                literal: literal.clone(),
+                checked: false,
+            }
+        }
+        [TokenTree::Punct(first), TokenTree::Punct(second)] if first.as_char() == '.' && second.as_char() == '.' => {
+            Segment::Literal {
+                literal: spanned(Literal::string(".."), first.span()),
+                checked: false,
+            }
+        }
+        [TokenTree::Punct(dot)] if dot.as_char() == '.' => Segment::Literal {
+            literal: spanned(Literal::string("."), dot.span()),
+            checked: false,
+        },
+        _ => Segment::Expr(std::mem::take(&mut tokens)),
+    }
+}
+
+fn is_string(literal: &Literal) -> bool {
+    let text = literal.to_string();
+    text.starts_with('"') || text.starts_with("r\"") || text.starts_with("r#")
+}
+
+/// `{ checks; concat!(literals...) }`, where each check points at its own literal.
+fn fold_run(krate: &Crate, run: Run) -> TokenTree {
+    let mut block = TokenStream::new();
+    let mut parts = TokenStream::new();
+    if run.root {
+        parts.extend(krate.path("::separator!(),"));
+    }
+    for (index, (literal, checked)) in run.literals.into_iter().enumerate() {
+        if checked {
+            // `$crate` is resolved with its own span, so it is kept in a group with the span of the literal.
+            let span = literal.span();
+            block.extend(respan(parse("const _: () ="), span));
+            block.extend(TokenStream::from(group(
+                Delimiter::Parenthesis,
+                krate.path("::check_literal_segment"),
+                span,
+            )));
+            let mut arguments = literal_tokens(&Literal::string(&literal.to_string()));
+            arguments.extend(parse(","));
+            arguments.extend(literal_tokens(&literal));
+            block.extend(respan(group_tokens(Delimiter::Parenthesis, arguments), span));
+            block.extend(respan(parse(";"), span));
+        }
+        if index != 0 {
+            parts.extend(krate.path("::separator!(),"));
+        }
+        parts.extend(literal_tokens(&literal));
+        parts.extend(parse(","));
+    }
+    block.extend(parse("::std::concat!"));
+    block.extend(group_tokens(Delimiter::Parenthesis, parts));
+    group(Delimiter::Brace, block, Span::call_site())
+}
+
+/// Appends `/ $crate::path_impl!(@hint $segment)` to the chain.
+fn push_segment(krate: &Crate, chain: &mut TokenStream, segment: TokenTree) {
+    let mut hint = parse("@hint");
+    hint.extend(TokenStream::from(segment));
+    chain.extend(parse("/"));
+    chain.extend(krate.path("::path_impl!"));
+    chain.extend(group_tokens(Delimiter::Parenthesis, hint));
+}
+
+/// Path of `path-dsl`, as given by the `$crate` of `path_dsl::slash::path!`.
+struct Crate(TokenStream);
+
+impl Crate {
+    /// `$crate` followed by `rest`, which starts with `::`.
+    fn path(&self, rest: &str) -> TokenStream {
+        let mut path = self.0.clone();
+        path.extend(parse(rest));
+        path
+    }
+}
+
+fn parse(code: &str) -> TokenStream {
+    code.parse().expect("generated code is valid")
+}
+
+fn literal_tokens(literal: &Literal) -> TokenStream {
+    TokenStream::from(TokenTree::from(literal.clone()))
+}
+
+fn group(delimiter: Delimiter, stream: TokenStream, span: Span) -> TokenTree {
+    let mut group = Group::new(delimiter, stream);
+    group.set_span(span);
+    TokenTree::from(group)
+}
+
+fn group_tokens(delimiter: Delimiter, stream: TokenStream) -> TokenStream {
+    TokenStream::from(group(delimiter, stream, Span::call_site()))
+}
+
+fn spanned(mut literal: Literal, span: Span) -> Literal {
+    literal.set_span(span);
+    literal
+}
+
+/// Moves every token of `stream` to `span`, so errors in it point there.
+fn respan(stream: TokenStream, span: Span) -> TokenStream {
+    stream
+        .into_iter()
+        .map(|token| match token {
+            TokenTree::Group(inner) => group(inner.delimiter(), respan(inner.stream(), span), span),
+            mut token => {
+                token.set_span(span);
+                token
+            }
+        })
+        .collect()
+}
@@ -55,7 +55,8 @@
 //!
 //! PathDSL's [`path!`](macro.path.html) macro allows for the creation of a `PathBuf` in the most efficent way possible in the situation.
 //!
-//! note the use of `|` instead of `/` due to rust's macro rules
+//! note the use of `|` instead of `/` due to rust's macro rules. The optional `proc-macro` feature adds
+//! [`slash::path!`](slash/macro.path.html), which takes `/`.
 //!
 //! ```rust
 //! use path_dsl::path;
@@ -90,10 +91,25 @@
 //! # assert_eq!(path, path2);
 //! ```
 //!
+//! A `PathDSL` can also be appended to in place with `/=`, or `|=` to mirror the syntax of `path!`:
+//!
+//! ```rust
+//! use path_dsl::PathDSL;
+//! # use std::path::PathBuf;
+//! let mut path = PathDSL::from("logs");
+//! for day in 1..=3 {
+//!     path /= day;
+//! }
+//! path |= "latest.log";
+//! # assert_eq!(path, PathBuf::from("logs").join("1").join("2").join("3").join("latest.log"));
+//! ```
+//!
 //! ### Adding Path-Like Structures
 //!
 //! As well as using regular string literals, you can use anything that can be passed to `PathBuf::push`
-//! as a part of the DSL.
+//! as a part of the DSL. Shared paths like `Rc<Path>`, `Arc<Path>` and `Arc<OsStr>`, as well as `Box<OsStr>`
+//! and single `char`s, can be used by value too. Integers are formatted straight into the path, and your own
+//! types can be used by implementing [`PathSegment`](trait.PathSegment.html).
 //!
 //! Note the borrow on `other`: as these types are not `Copy`, they will be moved
 //! into the path unless you borrow them. This matches behavior with `PathBuf::push`, but can be surprising
@@ -118,6 +134,31 @@
 //! # assert_eq!(mac, path2);
 //! ```
 //!
+//! Segments aren't limited to variables and literals. Method calls, field accesses, indexing,
+//! function calls and `?` all work without any extra parentheses:
+//!
+//! ```rust
+//! use path_dsl::path;
+//! # use std::path::PathBuf;
+//!
+//! struct Config {
+//!     root: PathBuf,
+//! }
+//!
+//! let config = Config { root: PathBuf::from("root") };
+//! let args = vec![String::from("arg0"), String::from("arg1")];
+//!
+//! let p = path!(&config.root | &args[1] | config.root.join("nested") | args.last().unwrap() | "file.txt");
+//!
+//! # let mut p2 = PathBuf::new();
+//! # p2.push("root");
+//! # p2.push("arg1");
+//! # p2.push("root/nested");
+//! # p2.push("arg1");
+//! # p2.push("file.txt");
+//! # assert_eq!(p, p2);
+//! ```
+//!
 //! ### Moving vs Borrowing
 //!
 //! Both the macro and the DSL type behave the same with regard to borrowing vs moving. If a
@@ -157,6 +198,372 @@
 //! # assert_eq!(mac, path2);
 //! ```
 //!
+//! ### Optional Segments
+//!
+//! Prefixing a segment with `?` makes it optional: an `Option` that is appended when it is `Some`
+//! and skipped when it is `None`. `PathDSL` also supports `/` with any `Option` of a type it
+//! supports.
+//!
+//! ```rust
+//! use path_dsl::path;
+//! # use std::path::PathBuf;
+//!
+//! let profile: Option<&str> = Some("release");
+//! let none: Option<&str> = None;
+//!
+//! assert_eq!(path!("target" | ?profile | "bin"), PathBuf::from("target").join("release").join("bin"));
+//! assert_eq!(path!("target" | ?none | "bin"), PathBuf::from("target").join("bin"));
+//! ```
+//!
+//! ### Splatting Iterators
+//!
+//! Prefixing a segment with `..` splices every item of an iterator into the path. Anything that
+//! `PathBuf` can be extended with works, including arrays, slices, `Vec`s, and the iterators returned by
+//! `Path::components` and `Path::iter`.
+//!
+//! ```rust
+//! use path_dsl::path;
+//! # use std::path::{Path, PathBuf};
+//!
+//! let parts = vec![String::from("a"), String::from("b")];
+//! let p = path!("root" | ..parts | "index.html");
+//! # let mut p2 = PathBuf::new();
+//! # p2.push("root");
+//! # p2.push("a");
+//! # p2.push("b");
+//! # p2.push("index.html");
+//! # assert_eq!(p, p2);
+//!
+//! let nested = Path::new("c/d");
+//! let p = path!(..nested.components() | "e");
+//! # assert_eq!(p, Path::new("c").join("d").join("e"));
+//! ```
+//!
+//! ### Formatted Segments
+//!
+//! A segment written as `{"format string", args...}` takes the same arguments as `format!`, but is
+//! written straight into the path instead of going through a temporary `String`.
+//!
+//! ```rust
+//! use path_dsl::path;
+//! # use std::path::PathBuf;
+//!
+//! let frame = 42;
+//! let id = "abc";
+//! let p = path!("out" | {"frame_{:04}.png", frame});
+//! # assert_eq!(p, PathBuf::from("out").join("frame_0042.png"));
+//! let log = path!("logs" | {"run-{id}.log"});
+//! # assert_eq!(log, PathBuf::from("logs").join("run-abc.log"));
+//! ```
+//!
+//! ### Conditional Segments
+//!
+//! Runtime conditions can choose between segments, or skip them entirely:
+//!
+//! ```rust
+//! use path_dsl::path;
+//! # use std::path::PathBuf;
+//!
+//! let release = true;
+//! let verbose = false;
+//! let bin = "app";
+//!
+//! let p = path!("target" | if release { "release" } else { "debug" } | bin);
+//! # assert_eq!(p, PathBuf::from("target").join("release").join("app"));
+//! let logs = path!("root" | if verbose => "logs" | "out.txt");
+//! # assert_eq!(logs, PathBuf::from("root").join("out.txt"));
+//! ```
+//!
+//! ### Platform-Specific Segments
+//!
+//! Segments can be prefixed with `#[cfg(...)]` to include them only on certain targets. Segments that are
+//! configured out don't exist in the expansion at all, so the remaining literals are still concatenated.
+//!
+//! ```rust
+//! use path_dsl::path;
+//! # use std::path::PathBuf;
+//!
+//! let home = PathBuf::from("home");
+//! let config = path!(home | #[cfg(windows)] "AppData" | #[cfg(windows)] "Roaming" | #[cfg(not(windows))] ".config" | "app");
+//! # #[cfg(windows)]
+//! # assert_eq!(config, PathBuf::from("home").join("AppData").join("Roaming").join("app"));
+//! # #[cfg(not(windows))]
+//! # assert_eq!(config, PathBuf::from("home").join(".config").join("app"));
+//! ```
+//!
+//! ### Roots and Relative Directories
+//!
+//! `/` at the start of a path is the root of the filesystem, and `..` and `.` are the parent and current
+//! directory. Windows drives are written as `C:`, and network shares as `unc "server" "share"`. These
+//! expand to the right separators for the target. Drives don't exist on other targets, so they fail to
+//! compile there unless the segment is behind `#[cfg(windows)]`.
+//!
+//! ```rust
+//! use path_dsl::path;
+//! # use std::path::PathBuf;
+//!
+//! let etc = path!(/ "etc" | "app");
+//! let up = path!(.. | .. | "lib");
+//! let share = path!(unc "server" "share" | "dir");
+//! #[cfg(windows)]
+//! let users = path!(C: | "Users");
+//! # assert_eq!(up, PathBuf::from("..").join("..").join("lib"));
+//! # #[cfg(windows)]
+//! # {
+//! #     assert_eq!(etc, PathBuf::from("\\etc\\app"));
+//! #     assert_eq!(users, PathBuf::from("C:\\Users"));
+//! #     assert_eq!(share, PathBuf::from("\\\\server\\share\\dir"));
+//! # }
+//! # #[cfg(not(windows))]
+//! # {
+//! #     assert_eq!(etc, PathBuf::from("/etc/app"));
+//! #     assert_eq!(share, PathBuf::from("//server/share/dir"));
+//! # }
+//! ```
+//!
+//! A drive must be a single letter:
+//!
+//! ```rust,compile_fail
+//! use path_dsl::path;
+//!
+//! let users = path!(foo: | "Users"); // expected a drive letter
+//! ```
+//!
+//! ### Output Types
+//!
+//! `path!` evaluates to a `PathBuf` by default. Ending it with `=> Type` converts the finished buffer
+//! straight into any type `PathDSL` converts into, such as `OsString`, `Box<Path>`, `Arc<Path>`,
+//! `Rc<Path>` or `Cow<Path>`, without going through a `PathBuf` first.
+//!
+//! ```rust
+//! use path_dsl::path;
+//! # use std::path::{Path, PathBuf};
+//! # use std::sync::Arc;
+//!
+//! let key: Arc<Path> = path!("cache" | "entry" => Arc<Path>);
+//! # assert_eq!(&*key, PathBuf::from("cache").join("entry"));
+//! ```
+//!
+//! ### Reusing Buffers
+//!
+//! [`path_push!`](macro.path_push.html) appends segments to an existing `PathBuf` or `PathDSL`, and
+//! [`path_into!`](macro.path_into.html) clears one and builds a new path in it, keeping its capacity.
+//!
+//! ```rust
+//! use path_dsl::{path_into, path_push};
+//! # use std::path::PathBuf;
+//!
+//! let mut buf = PathBuf::from("root");
+//! path_push!(buf | "dir" | "file.txt");
+//! path_into!(buf; "other" | "file.txt");
+//! # assert_eq!(buf, PathBuf::from("other").join("file.txt"));
+//! ```
+//!
+//! ### Static Paths
+//!
+//! When every segment is a literal, [`static_path!`](macro.static_path.html) evaluates to a
+//! `&'static Path` without allocating, and [`static_path_str!`](macro.static_path_str.html) to a
+//! `&'static str` that can be used in `const` items and `include_str!`.
+//!
+//! ```rust
+//! use path_dsl::{static_path, static_path_str};
+//! # use std::path::{Path, PathBuf};
+//!
+//! const CONFIG: &str = static_path_str!("app" | "config.toml");
+//! let config: &'static Path = static_path!("etc" | "app" | "config.toml");
+//! # assert_eq!(config, PathBuf::from("etc").join("app").join("config.toml"));
+//! ```
+//!
+//! ### Checked Literals
+//!
+//! Literal segments are checked at compile time. A literal that contains a separator or nul, starts or ends
+//! with whitespace, starts with a drive, or is a reserved device name on Windows would silently turn into
+//! something other than a single component, so it fails to compile. This is especially important for
+//! absolute literals, as pushing one throws away everything before it.
+//!
+//! ```rust,compile_fail
+//! use path_dsl::path;
+//!
+//! let path = path!("home" | "/etc" | "app");
+//! ```
+//!
+//! Prefix the literal with `raw` if more than one component is intended.
+//!
+//! ```rust
+//! use path_dsl::path;
+//! # use std::path::PathBuf;
+//!
+//! let path = path!("usr" | raw "local/bin");
+//! # assert_eq!(path, PathBuf::from("usr").join("local/bin"));
+//! ```
+//!
+//! ### Common Mistakes
+//!
+//! Segments are separated by `|`. Using `/`, `,` or `;` instead, leaving a segment empty with `||`, or ending
+//! the path with a `|` is reported with a single error saying so.
+//!
+//! ```rust,compile_fail
+//! use path_dsl::path;
+//!
+//! let path = path!("home" / "user"); // use `|` not `/` between path segments
+//! ```
+//!
+//! ```rust,compile_fail
+//! use path_dsl::path;
+//!
+//! let path = path!("home", "user"); // use `|` not `,` between path segments
+//! ```
+//!
+//! ```rust,compile_fail
+//! use path_dsl::path;
+//! let user = "user";
+//!
+//! let path = path!("home" || user); // empty path segment between `||`, use a single `|`
+//! ```
+//!
+//! ```rust,compile_fail
+//! use path_dsl::path;
+//!
+//! let path = path!("home" | | "user"); // empty path segment before `|`
+//! ```
+//!
+//! ```rust,compile_fail
+//! use path_dsl::path;
+//!
+//! let path = path!("home" | "user" |); // trailing `|` after the last path segment
+//! ```
+//!
+//! ```rust,compile_fail
+//! use path_dsl::path;
+//!
+//! let path = path!("home" "user"); // missing `|` between path segments
+//! ```
+//!
+//! ### Extensions and Suffixes
+//!
+//! A segment can be followed by `. "ext"` to append an extension, `+ "suffix"` to append raw text to it,
+//! or `% "ext"` to replace its extension. When the segment and its modifiers are literals, `.` and `+` are
+//! folded into the literal at compile time. Runtime strings work too, in parentheses after `.` unless the
+//! segment is a literal. With an integer instead of a string, `+` and `%` are arithmetic, and a number
+//! after `.` is a tuple field, like `pair.0`.
+//!
+//! ```rust
+//! use path_dsl::path;
+//! # use std::path::PathBuf;
+//!
+//! let stem = "archive";
+//! let name = PathBuf::from("data.csv");
+//! let part = 1;
+//! let ext = String::from("zip");
+//! assert_eq!(path!("dir" | stem . "tar.gz"), PathBuf::from("dir").join("archive.tar.gz"));
+//! assert_eq!(path!("dir" | stem . (ext)), PathBuf::from("dir").join("archive.zip"));
+//! assert_eq!(path!("dir" | "file" + "_v2" . "json"), PathBuf::from("dir").join("file_v2.json"));
+//! assert_eq!(path!("dir" | name % "json"), PathBuf::from("dir").join("data.json"));
+//! assert_eq!(path!("dir" | part + 1 . "log"), PathBuf::from("dir").join("2.log"));
+//! ```
+//!
+//! `PathDSL` has the same operators: `+` appends a raw suffix to the last component and `%` replaces its
+//! extension. As `+` binds looser than `/`, it applies to the whole path before it. They take strings, and
+//! panic if the string contains a separator.
+//!
+//! ```rust
+//! use path_dsl::PathDSL;
+//! # use std::path::PathBuf;
+//!
+//! let backup = PathDSL::from("dir") / "config.toml" + ".bak";
+//! assert_eq!(backup, PathBuf::from("dir").join("config.toml.bak"));
+//! let archive = PathDSL::from("dir") / "notes.txt" % "tar" + ".gz";
+//! assert_eq!(archive, PathBuf::from("dir").join("notes.tar.gz"));
+//! ```
+//!
+//! ### Fallible Segments
+//!
+//! When segments come from fallible sources, [`try_path!`](macro.try_path.html) accepts `Option`s and
+//! `Result`s as segments, next to any segment `path!` accepts, and evaluates to a
+//! `Result<PathBuf, PathBuildError>` which records the index of the segment that failed.
+//!
+//! ```rust
+//! use path_dsl::try_path;
+//! # use std::path::PathBuf;
+//!
+//! let p = try_path!(std::env::var_os("PATH_DSL_UNSET_VAR") | "cache");
+//! assert_eq!(p.unwrap_err().segment(), 0);
+//! ```
+//!
+//! ### Environment Variables
+//!
+//! [`env_path!`](macro.env_path.html) expands `$VAR`, `${VAR}` and `${VAR:-default}` in its string
+//! literals at runtime, and reports the name of any missing variable.
+//!
+//! ```rust
+//! use path_dsl::env_path;
+//! # use path_dsl::expand_env;
+//! # use std::path::PathBuf;
+//!
+//! let cache = env_path!("${XDG_CACHE_HOME:-$HOME/.cache}" | "app");
+//! # let expected = expand_env("${XDG_CACHE_HOME:-$HOME/.cache}").map(|dir| PathBuf::from(dir).join("app"));
+//! # assert_eq!(cache.ok(), expected.ok());
+//! ```
+//!
+//! ### Home Directories
+//!
+//! `~` at the start of a path is the home directory of the current user, and `~user` the home directory
+//! of `user` where it can be found. They are accepted by `try_path!` and `env_path!`, which report a
+//! missing home directory as a failed segment, but not by the infallible `path!`. An existing path can be expanded with
+//! [`PathDSL::expand_home`](struct.PathDSL.html#method.expand_home), and
+//! [`expand_home_in`](struct.PathDSL.html#method.expand_home_in) takes the environment as an
+//! [`EnvContext`](trait.EnvContext.html) instead of reading the process.
+//!
+//! ```rust
+//! use path_dsl::{try_path, PathDSL};
+//! # use path_dsl::{EnvContext, ProcessEnv};
+//! # use std::ffi::OsString;
+//! # use std::path::PathBuf;
+//!
+//! let config = try_path!(~ | ".config" | "app");
+//! # let expected = ProcessEnv.home_dir(None).map(|home| home.join(".config").join("app"));
+//! # assert_eq!(config.ok(), expected.ok());
+//!
+//! let env = |name: &str| match name {
+//!     "HOME" | "USERPROFILE" => Some(OsString::from("/home/you")),
+//!     _ => None,
+//! };
+//! let cache = PathDSL::from("~/.cache").expand_home_in(&env).unwrap();
+//! # assert_eq!(cache, PathBuf::from("/home/you/.cache"));
+//! ```
+//!
+//! ### Many Paths at Once
+//!
+//! [`paths!`](macro.paths.html) takes groups of alternatives in braces, and iterates over every path they
+//! make, like brace expansion in a shell. Alternatives can be literals, expressions, integer ranges or
+//! `..iter`.
+//!
+//! ```rust
+//! use path_dsl::paths;
+//! # use std::path::PathBuf;
+//!
+//! for shard in paths!("data" | "shard_" + {0..4} | {"index", "values"} . "bin") {
+//!     // data/shard_0/index.bin, data/shard_0/values.bin, data/shard_1/index.bin, ...
+//! #   assert!(shard.starts_with("data"));
+//! }
+//! ```
+//!
+//! ### Matching Paths
+//!
+//! [`match_path!`](macro.match_path.html) goes the other way, matching a path against patterns of its
+//! components.
+//!
+//! ```rust
+//! use path_dsl::{match_path, path};
+//!
+//! let p = path!("src" | "net" | "mod.rs");
+//! let module = match_path!(p, {
+//!     ["src", module: &str, "mod.rs"] => Some(module),
+//!     _ => None,
+//! });
+//! assert_eq!(module, Some("net"));
+//! ```
+//!
 //! ### PathDSL <=> PathBuf
 //!
 //! **The PathDSL type is not meant to be used directly, but exists to allow the macro to work.
@@ -236,7 +643,7 @@
 //!
 //! **First-Argument Optimization:**
 //!
-//! When the very first argument of the [`path!`](macro.path.html) macro is a owning `PathBuf`, `OsString` or `PathDSL`
+//! When the very first argument of the [`path!`](macro.path.html) macro is a owning `PathBuf`, `OsString`, `String`, `Box<OsStr>` or `PathDSL`
 //! passed by value (moved), instead of copying everything into a new `PathDSL`, it will just steal the
 //! buffer from that moved-in value. This allows you to use the [`path!`](macro.path.html) macro fearlessly when
 //! appending to already existing variables.
@@ -266,6 +673,7 @@
 
 #![allow(clippy::cognitive_complexity)]
 #![allow(clippy::float_cmp)]
+#![allow(clippy::from_over_into)]
 #![deny(nonstandard_style)]
 #![deny(future_incompatible)]
 #![deny(rust_2018_idioms)]
@@ -274,12 +682,16 @@
 #![warn(unused)]
 
 use std::borrow::{Borrow, Cow};
+use std::cell::Cell;
 use std::cmp::Ordering;
 use std::convert::Infallible;
+use std::error::Error;
 use std::ffi::{OsStr, OsString};
+use std::fmt;
 use std::hash::{Hash, Hasher};
-use std::ops::{Deref, DerefMut, Div};
-use std::path::{Iter, Path, PathBuf};
+use std::mem;
+use std::ops::{Add, BitOrAssign, Deref, DerefMut, Div, DivAssign, Rem};
+use std::path::{Component, Iter, Path, PathBuf};
 use std::rc::Rc;
 use std::str::FromStr;
 use std::sync::Arc;
@@ -287,6 +699,63 @@ use std::sync::Arc;
 #[cfg(test)]
 mod tests;
 
+/// `/` syntax for `path!`, enabled by the `proc-macro` feature.
+///
+/// [`slash::path!`](macro.path.html) takes `/` between segments instead of `|`. It is a procedural macro
+/// from the `path-dsl-macros` crate, so segments can be any expression, errors point at the segment that
+/// caused them, and any number of literals are folded into one. It expands to the same `CopylessDSL`
+/// and `PathDSL` operations as [`path!`](../macro.path.html), and returns a `PathBuf` the same way.
+///
+/// ```rust
+/// use path_dsl::slash::path;
+/// # use std::path::PathBuf;
+///
+/// let root = PathBuf::from("root");
+/// let names = ["a", "b"];
+/// let p = path!(root / "dir" / names.join("_") / "file.txt");
+/// # assert_eq!(p, PathBuf::from("root").join("dir").join("a_b").join("file.txt"));
+/// ```
+///
+/// A leading `/` is the root of the filesystem, `..` and `.` are the parent and current directory,
+/// and `raw "literal"` skips the literal check, as in `path!`. A trailing `=> Type` converts the path.
+/// Segments are split on every `/` outside of brackets, so division inside a segment needs
+/// parentheses.
+///
+/// ```rust
+/// use path_dsl::slash::path;
+/// # use std::ffi::OsString;
+/// # use std::path::PathBuf;
+///
+/// let count = 12;
+/// let p = path!(/ "pages" / (count / 10).to_string() => OsString);
+/// # assert_eq!(PathBuf::from(p), PathBuf::from(format!("{}pages", std::path::MAIN_SEPARATOR)).join("1"));
+/// ```
+///
+/// Literal segments are checked like they are in `path!`, and the error points at the literal.
+///
+/// ```rust,compile_fail
+/// use path_dsl::slash::path;
+/// let p = path!("dir" / "a/b");
+/// ```
+#[cfg(feature = "proc-macro")]
+pub mod slash {
+    #[doc(hidden)]
+    pub use path_dsl_macros::path as path_impl;
+
+    /// Builds a path from segments separated by `/`. See the [module documentation](index.html).
+    #[doc(hidden)]
+    #[macro_export]
+    macro_rules! slash_path {
+        ( $($tokens:tt)* ) => {
+            $crate::slash::path_impl!($crate; $($tokens)*)
+        };
+    }
+
+    // The proc macro can't name this crate itself, so it is given `$crate` by a `macro_rules!` wrapper.
+    #[doc(inline)]
+    pub use crate::slash_path as path;
+}
+
 /// A PathBuf wrapper that has support for a Path DSL.
 ///
 /// It is usable nearly identically to a PathBuf.
@@ -326,6 +795,116 @@ impl PathDSL {
     pub fn into_pathbuf(self) -> PathBuf {
         self.into()
     }
+
+    /// Appends `.extension` to the last component, keeping any extension it already has.
+    #[doc(hidden)]
+    #[inline(always)]
+    pub fn append_extension<S: AsRef<OsStr>>(mut self, extension: S) -> Self {
+        let path = self.path.as_mut_os_string();
+        path.push(".");
+        path.push(extension);
+        self
+    }
+
+    /// Appends a raw suffix to the last component without a separator.
+    #[doc(hidden)]
+    #[inline(always)]
+    pub fn append_suffix<S: AsRef<OsStr>>(mut self, suffix: S) -> Self {
+        self.path.as_mut_os_string().push(suffix);
+        self
+    }
+
+    /// Forwarder for [`PathBuf::set_extension`](https://doc.rust-lang.org/std/path/struct.PathBuf.html#method.set_extension)
+    /// that takes and returns `self`.
+    #[doc(hidden)]
+    #[inline(always)]
+    pub fn replace_extension<S: AsRef<OsStr>>(mut self, extension: S) -> Self {
+        self.path.set_extension(extension);
+        self
+    }
+
+    /// Replaces a leading `~` or `~user` with the home directory from the environment of the process.
+    ///
+    /// Paths that don't start with `~` are returned unchanged. See
+    /// [`EnvContext::home_dir`](trait.EnvContext.html#method.home_dir) for how the home directory is found.
+    ///
+    /// ```rust
+    /// # use path_dsl::{EnvContext, PathDSL, ProcessEnv};
+    /// let path = PathDSL::from("~/projects").expand_home();
+    /// let home = ProcessEnv.home_dir(None);
+    /// assert_eq!(path.ok(), home.ok().map(|home| PathDSL::from(home) / "projects"));
+    /// ```
+    pub fn expand_home(self) -> Result<Self, UnknownHome> {
+        self.expand_home_in(&ProcessEnv)
+    }
+
+    /// Version of [`expand_home`](#method.expand_home) which resolves the home directory from `env`.
+    ///
+    /// ```rust
+    /// # use path_dsl::PathDSL;
+    /// # use std::ffi::OsString;
+    /// # use std::path::PathBuf;
+    /// let env = |name: &str| match name {
+    ///     "HOME" | "USERPROFILE" => Some(OsString::from("/home/me")),
+    ///     _ => None,
+    /// };
+    /// let path = PathDSL::from("~/projects").expand_home_in(&env).unwrap();
+    /// assert_eq!(path, PathBuf::from("/home/me/projects"));
+    /// ```
+    pub fn expand_home_in<E>(self, env: &E) -> Result<Self, UnknownHome>
+    where
+        E: EnvContext + ?Sized,
+    {
+        let mut components = self.path.components();
+        let user = match components.next() {
+            Some(Component::Normal(first)) => match first.to_str() {
+                Some(first) if first.starts_with('~') => &first[1..],
+                _ => return Ok(self),
+            },
+            _ => return Ok(self),
+        };
+
+        let mut home = env.home_dir(if user.is_empty() { None } else { Some(user) })?;
+        let rest = components.as_path();
+        if !rest.as_os_str().is_empty() {
+            home.push(rest);
+        }
+        Ok(PathDSL { path: home })
+    }
+
+    /// Formats a new segment directly onto the end of the path without an intermediate `String`.
+    ///
+    /// Like `PathBuf::push`, a formatted segment that starts with a root or prefix replaces the path.
+    fn push_fmt(&mut self, args: fmt::Arguments<'_>) {
+        struct OsStringWriter<'a>(&'a mut OsString);
+
+        impl fmt::Write for OsStringWriter<'_> {
+            #[inline(always)]
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                self.0.push(s);
+                Ok(())
+            }
+        }
+
+        // Pushing an empty path adds a separator if one is needed, and nothing otherwise.
+        self.path.push("");
+        let start = self.path.as_os_str().len();
+        fmt::write(&mut OsStringWriter(self.path.as_mut_os_string()), args)
+            .expect("a Display implementation returned an error unexpectedly");
+
+        // Only `write_str` appended after `start`, so the segment is UTF-8. A rooted segment is rare, so it
+        // is copied out and pushed again to get the same result as `PathBuf::push`.
+        let segment = std::str::from_utf8(&self.path.as_os_str().as_encoded_bytes()[start..])
+            .expect("formatted segment is UTF-8");
+        match Path::new(segment).components().next() {
+            Some(Component::RootDir) | Some(Component::Prefix(_)) => {
+                let segment = segment.to_owned();
+                while self.path.pop() {}
+                self.path.push(segment);
+            }
+            _ => {}
+        }
+    }
 }
 
 //////////////////////////////////
@@ -449,28 +1028,64 @@ impl From<Cow<'_, OsStr>> for PathDSL {
     }
 }
 
-//////////
-// Into //
-//////////
-// We can't implement from on these types, so the best we can do is Into.
-
-impl Into<PathBuf> for PathDSL {
+impl From<Box<OsStr>> for PathDSL {
     #[inline(always)]
-    fn into(self) -> PathBuf {
-        self.path
+    fn from(other: Box<OsStr>) -> Self {
+        PathDSL {
+            path: PathBuf::from(other.into_os_string()),
+        }
     }
 }
 
-impl Into<OsString> for PathDSL {
+impl From<Rc<Path>> for PathDSL {
     #[inline(always)]
-    fn into(self) -> OsString {
-        self.into_os_string()
+    fn from(other: Rc<Path>) -> Self {
+        PathDSL {
+            path: PathBuf::from(&*other),
+        }
     }
 }
 
-impl Into<Box<Path>> for PathDSL {
+impl From<Arc<Path>> for PathDSL {
     #[inline(always)]
-    fn into(self) -> Box<Path> {
+    fn from(other: Arc<Path>) -> Self {
+        PathDSL {
+            path: PathBuf::from(&*other),
+        }
+    }
+}
+
+impl From<Arc<OsStr>> for PathDSL {
+    #[inline(always)]
+    fn from(other: Arc<OsStr>) -> Self {
+        PathDSL {
+            path: PathBuf::from(&*other),
+        }
+    }
+}
+
+//////////
+// Into //
+//////////
+// We can't implement from on these types, so the best we can do is Into.
+
+impl Into<PathBuf> for PathDSL {
+    #[inline(always)]
+    fn into(self) -> PathBuf {
+        self.path
+    }
+}
+
+impl Into<OsString> for PathDSL {
+    #[inline(always)]
+    fn into(self) -> OsString {
+        self.into_os_string()
+    }
+}
+
+impl Into<Box<Path>> for PathDSL {
+    #[inline(always)]
+    fn into(self) -> Box<Path> {
         self.into_boxed_path()
     }
 }
@@ -496,14 +1111,14 @@ impl<'a> Into<Cow<'a, OsStr>> for &'a PathDSL {
     }
 }
 
-impl<'a> Into<Arc<Path>> for PathDSL {
+impl Into<Arc<Path>> for PathDSL {
     #[inline(always)]
     fn into(self) -> Arc<Path> {
         self.path.into()
     }
 }
 
-impl<'a> Into<Rc<Path>> for PathDSL {
+impl Into<Rc<Path>> for PathDSL {
     #[inline(always)]
     fn into(self) -> Rc<Path> {
         self.path.into()
@@ -576,7 +1191,7 @@ impl Eq for PathDSL {}
 impl PartialOrd<PathDSL> for PathDSL {
     #[inline(always)]
     fn partial_cmp(&self, other: &PathDSL) -> Option<Ordering> {
-        self.path.partial_cmp(&other.path)
+        Some(self.cmp(other))
     }
 }
 
@@ -605,7 +1220,7 @@ impl<'a> PartialOrd<Cow<'a, OsStr>> for PathDSL {
     //noinspection RsTypeCheck
     #[inline(always)]
     fn partial_cmp(&self, other: &Cow<'a, OsStr>) -> Option<Ordering> {
-        self.path.as_path().partial_cmp(&*other)
+        self.path.as_path().partial_cmp(other)
     }
 }
 
@@ -613,7 +1228,7 @@ impl PartialOrd<OsStr> for PathDSL {
     //noinspection RsTypeCheck
     #[inline(always)]
     fn partial_cmp(&self, other: &OsStr) -> Option<Ordering> {
-        self.path.as_path().partial_cmp(&*other)
+        self.path.as_path().partial_cmp(other)
     }
 }
 
@@ -621,7 +1236,7 @@ impl PartialOrd<OsString> for PathDSL {
     //noinspection RsTypeCheck
     #[inline(always)]
     fn partial_cmp(&self, other: &OsString) -> Option<Ordering> {
-        self.path.as_path().partial_cmp(&*other)
+        self.path.as_path().partial_cmp(other)
     }
 }
 
@@ -699,227 +1314,325 @@ impl Borrow<Path> for PathDSL {
     }
 }
 
-/////////
-// Div //
-/////////
-
-impl Div<PathDSL> for PathDSL {
-    type Output = PathDSL;
+///////////////////
+// Path Segments //
+///////////////////
 
+/// Something which can be appended to a path as a new segment.
+///
+/// This is what `PathDSL`'s `Div` and all the macros accept. It is implemented for references to anything
+/// `AsRef<Path>`, the owned string and path types, `Rc`/`Arc` paths, `char`, all the integer types,
+/// `format_args!` and `Option`s of segments. The owned types donate their buffer to a path which hasn't
+/// allocated one yet, and are pushed onto it otherwise so reserved capacity is kept.
+///
+/// Integers are formatted straight into the path's buffer, without going through a `String`:
+///
+/// ```rust
+/// use path_dsl::{path, PathDSL};
+/// # use std::path::PathBuf;
+///
+/// let id: u64 = 42;
+/// let p: PathBuf = path!("users" | id | "avatar.png");
+/// # assert_eq!(p, PathBuf::from("users").join("42").join("avatar.png"));
+/// let q = PathDSL::from("page") / 7;
+/// # assert_eq!(q, PathBuf::from("page").join("7"));
+/// ```
+///
+/// Types implementing `Display` opt in with [`display_segment!`](macro.display_segment.html), other types
+/// can implement it by hand.
+pub trait PathSegment {
+    /// Appends `self` to the end of `path`.
+    fn push_onto(self, path: &mut PathDSL);
+
+    /// Whether `push_onto` gives its own buffer to a path which hasn't allocated one yet, so the macros
+    /// don't allocate one for it first.
+    #[doc(hidden)]
     #[inline(always)]
-    fn div(mut self, rhs: PathDSL) -> Self::Output {
-        if self.path.as_os_str().is_empty() {
-            rhs
-        } else {
-            self.path.push(rhs);
-            self
-        }
+    fn donates_buffer(&self) -> bool {
+        false
     }
 }
 
-impl<T> Div<&T> for PathDSL
+/// Implements [`PathSegment`](trait.PathSegment.html) for types through their `Display` implementation.
+///
+/// The segment is formatted straight into the path's buffer. Like any other segment, it replaces the path
+/// if it starts with a root.
+///
+/// ```rust
+/// use path_dsl::{display_segment, path};
+/// # use std::path::PathBuf;
+/// use std::fmt;
+///
+/// struct UserId(u32);
+///
+/// impl fmt::Display for UserId {
+///     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+///         write!(f, "user-{}", self.0)
+///     }
+/// }
+///
+/// display_segment!(UserId);
+///
+/// let p: PathBuf = path!("home" | UserId(7) | "settings.toml");
+/// # assert_eq!(p, PathBuf::from("home").join("user-7").join("settings.toml"));
+/// ```
+#[macro_export]
+macro_rules! display_segment {
+    ($($ty:ty),+ $(,)?) => {
+        $(
+            impl $crate::PathSegment for $ty {
+                #[inline(always)]
+                fn push_onto(self, path: &mut $crate::PathDSL) {
+                    $crate::PathSegment::push_onto(format_args!("{}", self), path)
+                }
+            }
+        )+
+    };
+}
+
+impl<T> PathSegment for &T
 where
     T: AsRef<Path> + ?Sized,
 {
-    type Output = PathDSL;
-
     #[inline(always)]
-    fn div(mut self, rhs: &T) -> Self::Output {
-        self.path.push(rhs.as_ref());
-        self
+    fn push_onto(self, path: &mut PathDSL) {
+        // A path without a buffer yet gets one of exactly the right size.
+        if path.path.capacity() == 0 {
+            *path = PathDSL::from(self);
+        } else {
+            path.path.push(self.as_ref());
+        }
     }
 }
 
-impl<T> Div<&mut T> for PathDSL
+impl<T> PathSegment for &mut T
 where
     T: AsRef<Path> + ?Sized,
 {
-    type Output = PathDSL;
-
     #[inline(always)]
-    fn div(mut self, rhs: &mut T) -> Self::Output {
-        self.path.push(rhs.as_ref());
-        self
+    fn push_onto(self, path: &mut PathDSL) {
+        if path.path.capacity() == 0 {
+            *path = PathDSL::from(&*self);
+        } else {
+            path.path.push((*self).as_ref());
+        }
     }
 }
 
-impl Div<OsString> for PathDSL {
-    type Output = PathDSL;
-
+impl PathSegment for PathDSL {
     #[inline(always)]
-    fn div(mut self, rhs: OsString) -> Self::Output {
-        if self.path.as_os_str().is_empty() {
-            Self::from(rhs)
+    fn push_onto(self, path: &mut PathDSL) {
+        // A path with a buffer may have been reserved or cleared for reuse, so it is only replaced without one.
+        if path.path.capacity() == 0 {
+            *path = self;
         } else {
-            self.path.push(rhs);
-            self
+            path.path.push(self);
         }
     }
-}
 
-impl Div<String> for PathDSL {
-    type Output = PathDSL;
+    #[inline(always)]
+    fn donates_buffer(&self) -> bool {
+        true
+    }
+}
 
+impl PathSegment for OsString {
     #[inline(always)]
-    fn div(mut self, rhs: String) -> Self::Output {
-        if self.path.as_os_str().is_empty() {
-            Self::from(rhs)
+    fn push_onto(self, path: &mut PathDSL) {
+        if path.path.capacity() == 0 {
+            *path = PathDSL::from(self);
         } else {
-            self.path.push(rhs);
-            self
+            path.path.push(self);
         }
     }
-}
 
-impl Div<PathBuf> for PathDSL {
-    type Output = PathDSL;
+    #[inline(always)]
+    fn donates_buffer(&self) -> bool {
+        true
+    }
+}
 
+impl PathSegment for String {
     #[inline(always)]
-    fn div(mut self, rhs: PathBuf) -> Self::Output {
-        if self.path.as_os_str().is_empty() {
-            Self::from(rhs)
+    fn push_onto(self, path: &mut PathDSL) {
+        if path.path.capacity() == 0 {
+            *path = PathDSL::from(self);
         } else {
-            self.path.push(rhs);
-            self
+            path.path.push(self);
         }
     }
-}
-
-impl Div<Box<Path>> for PathDSL {
-    type Output = PathDSL;
 
     #[inline(always)]
-    fn div(mut self, rhs: Box<Path>) -> Self::Output {
-        self.path.push(rhs);
-        self
+    fn donates_buffer(&self) -> bool {
+        true
     }
 }
 
-impl Div<Cow<'_, Path>> for PathDSL {
-    type Output = PathDSL;
+impl PathSegment for PathBuf {
+    #[inline(always)]
+    fn push_onto(self, path: &mut PathDSL) {
+        if path.path.capacity() == 0 {
+            *path = PathDSL::from(self);
+        } else {
+            path.path.push(self);
+        }
+    }
 
     #[inline(always)]
-    fn div(mut self, rhs: Cow<'_, Path>) -> Self::Output {
-        self.path.push(rhs);
-        self
+    fn donates_buffer(&self) -> bool {
+        true
     }
 }
 
-impl Div<Cow<'_, OsStr>> for PathDSL {
-    type Output = PathDSL;
+impl PathSegment for Box<Path> {
+    #[inline(always)]
+    fn push_onto(self, path: &mut PathDSL) {
+        if path.path.capacity() == 0 {
+            *path = PathDSL::from(self);
+        } else {
+            path.path.push(self);
+        }
+    }
 
     #[inline(always)]
-    fn div(mut self, rhs: Cow<'_, OsStr>) -> Self::Output {
-        self.path.push(rhs);
-        self
+    fn donates_buffer(&self) -> bool {
+        true
     }
 }
 
-///////////
-// Div & //
-///////////
-
-impl Div<PathDSL> for &PathDSL {
-    type Output = PathDSL;
+impl PathSegment for Box<OsStr> {
+    #[inline(always)]
+    fn push_onto(self, path: &mut PathDSL) {
+        if path.path.capacity() == 0 {
+            *path = PathDSL::from(self);
+        } else {
+            path.path.push(&*self);
+        }
+    }
 
     #[inline(always)]
-    fn div(self, rhs: PathDSL) -> Self::Output {
-        let mut new_self = (*self).clone();
-        new_self.path.push(rhs);
-        new_self
+    fn donates_buffer(&self) -> bool {
+        true
     }
 }
 
-impl<T> Div<&T> for &PathDSL
-where
-    T: AsRef<Path> + ?Sized,
-{
-    type Output = PathDSL;
+impl PathSegment for Cow<'_, Path> {
+    #[inline(always)]
+    fn push_onto(self, path: &mut PathDSL) {
+        if path.path.capacity() == 0 {
+            *path = PathDSL::from(self);
+        } else {
+            path.path.push(self);
+        }
+    }
 
     #[inline(always)]
-    fn div(self, rhs: &T) -> Self::Output {
-        let mut new_self = (*self).clone();
-        new_self.path.push(rhs.as_ref());
-        new_self
+    fn donates_buffer(&self) -> bool {
+        matches!(self, Cow::Owned(_))
     }
 }
 
-impl<T> Div<&mut T> for &PathDSL
-where
-    T: AsRef<Path> + ?Sized,
-{
-    type Output = PathDSL;
+impl PathSegment for Cow<'_, OsStr> {
+    #[inline(always)]
+    fn push_onto(self, path: &mut PathDSL) {
+        if path.path.capacity() == 0 {
+            *path = PathDSL::from(self.into_owned());
+        } else {
+            path.path.push(self);
+        }
+    }
 
     #[inline(always)]
-    fn div(self, rhs: &mut T) -> Self::Output {
-        let mut new_self = (*self).clone();
-        new_self.path.push(rhs.as_ref());
-        new_self
+    fn donates_buffer(&self) -> bool {
+        matches!(self, Cow::Owned(_))
     }
 }
 
-impl Div<OsString> for &PathDSL {
-    type Output = PathDSL;
-
+impl PathSegment for Rc<Path> {
     #[inline(always)]
-    fn div(self, rhs: OsString) -> Self::Output {
-        let mut new_self = (*self).clone();
-        new_self.path.push(rhs);
-        new_self
+    fn push_onto(self, path: &mut PathDSL) {
+        path.path.push(&*self);
     }
 }
 
-impl Div<String> for &PathDSL {
-    type Output = PathDSL;
+impl PathSegment for Arc<Path> {
+    #[inline(always)]
+    fn push_onto(self, path: &mut PathDSL) {
+        path.path.push(&*self);
+    }
+}
 
+impl PathSegment for Arc<OsStr> {
     #[inline(always)]
-    fn div(self, rhs: String) -> Self::Output {
-        let mut new_self = (*self).clone();
-        new_self.path.push(rhs);
-        new_self
+    fn push_onto(self, path: &mut PathDSL) {
+        path.path.push(&*self);
     }
 }
 
-impl Div<PathBuf> for &PathDSL {
-    type Output = PathDSL;
+impl PathSegment for char {
+    #[inline(always)]
+    fn push_onto(self, path: &mut PathDSL) {
+        path.path.push(self.encode_utf8(&mut [0; 4]));
+    }
+}
 
+impl PathSegment for fmt::Arguments<'_> {
     #[inline(always)]
-    fn div(self, rhs: PathBuf) -> Self::Output {
-        let mut new_self = (*self).clone();
-        new_self.path.push(rhs);
-        new_self
+    fn push_onto(self, path: &mut PathDSL) {
+        path.push_fmt(self);
     }
 }
 
-impl Div<Box<Path>> for &PathDSL {
-    type Output = PathDSL;
+impl<T> PathSegment for Option<T>
+where
+    T: PathSegment,
+{
+    #[inline(always)]
+    fn push_onto(self, path: &mut PathDSL) {
+        if let Some(segment) = self {
+            segment.push_onto(path);
+        }
+    }
 
     #[inline(always)]
-    fn div(self, rhs: Box<Path>) -> Self::Output {
-        let mut new_self = (*self).clone();
-        new_self.path.push(rhs);
-        new_self
+    fn donates_buffer(&self) -> bool {
+        self.as_ref().is_some_and(T::donates_buffer)
     }
 }
 
-impl Div<Cow<'_, Path>> for &PathDSL {
+display_segment!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+
+/////////
+// Div //
+/////////
+
+impl<T> Div<T> for PathDSL
+where
+    T: PathSegment,
+{
     type Output = PathDSL;
 
     #[inline(always)]
-    fn div(self, rhs: Cow<'_, Path>) -> Self::Output {
-        let mut new_self = (*self).clone();
-        new_self.path.push(rhs);
-        new_self
+    fn div(mut self, rhs: T) -> Self::Output {
+        rhs.push_onto(&mut self);
+        self
     }
 }
 
-impl Div<Cow<'_, OsStr>> for &PathDSL {
+///////////
+// Div & //
+///////////
+
+impl<T> Div<T> for &PathDSL
+where
+    T: PathSegment,
+{
     type Output = PathDSL;
 
     #[inline(always)]
-    fn div(self, rhs: Cow<'_, OsStr>) -> Self::Output {
+    fn div(self, rhs: T) -> Self::Output {
         let mut new_self = (*self).clone();
-        new_self.path.push(rhs);
+        rhs.push_onto(&mut new_self);
         new_self
     }
 }
@@ -928,346 +1641,2243 @@ impl Div<Cow<'_, OsStr>> for &PathDSL {
 // Div &mut //
 //////////////
 
-impl Div<PathDSL> for &mut PathDSL {
+impl<T> Div<T> for &mut PathDSL
+where
+    T: PathSegment,
+{
     type Output = PathDSL;
 
     #[inline(always)]
-    fn div(self, rhs: PathDSL) -> Self::Output {
+    fn div(self, rhs: T) -> Self::Output {
         let mut new_self = (*self).clone();
-        new_self.path.push(rhs);
+        rhs.push_onto(&mut new_self);
         new_self
     }
 }
 
-impl<T> Div<&T> for &mut PathDSL
+///////////////
+// DivAssign //
+///////////////
+
+impl<T> DivAssign<T> for PathDSL
 where
-    T: AsRef<Path> + ?Sized,
+    T: PathSegment,
 {
-    type Output = PathDSL;
-
     #[inline(always)]
-    fn div(self, rhs: &T) -> Self::Output {
-        let mut new_self = (*self).clone();
-        new_self.path.push(rhs.as_ref());
-        new_self
+    fn div_assign(&mut self, rhs: T) {
+        rhs.push_onto(self);
     }
 }
 
-impl<T> Div<&mut T> for &mut PathDSL
+impl<T> BitOrAssign<T> for PathDSL
 where
-    T: AsRef<Path> + ?Sized,
+    T: PathSegment,
 {
-    type Output = PathDSL;
-
     #[inline(always)]
-    fn div(self, rhs: &mut T) -> Self::Output {
-        let mut new_self = (*self).clone();
-        new_self.path.push(rhs.as_ref());
-        new_self
+    fn bitor_assign(&mut self, rhs: T) {
+        rhs.push_onto(self);
     }
 }
 
-impl Div<OsString> for &mut PathDSL {
+/////////////////
+// Add and Rem //
+/////////////////
+
+/// Strings which `+` and `%` on `PathDSL` add to the last component.
+///
+/// Paths aren't accepted, as they could add components instead. `+` and `%` panic if the string contains
+/// a separator or nul, in every build.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` can't be added to the last component of a path",
+    label = "expected a `&str`, `&OsStr`, `String` or `OsString`",
+    note = "a path could add components, use `/` to append it as a segment"
+)]
+pub trait NamePart: AsRef<OsStr> {}
+
+impl NamePart for str {}
+impl NamePart for OsStr {}
+impl NamePart for String {}
+impl NamePart for OsString {}
+impl<T> NamePart for &T where T: NamePart + ?Sized {}
+
+/// Checks that `part` won't add a component to the path it is added to. This is checked in release
+/// builds too, so a path is the same whichever profile built it.
+#[inline(always)]
+fn check_name_part(part: &OsStr) {
+    assert!(
+        !contains_separator_or_nul(part.as_encoded_bytes()),
+        "suffix or extension contains a separator or nul"
+    );
+}
+
+impl<S> Add<S> for PathDSL
+where
+    S: NamePart,
+{
     type Output = PathDSL;
 
     #[inline(always)]
-    fn div(self, rhs: OsString) -> Self::Output {
-        let mut new_self = (*self).clone();
-        new_self.path.push(rhs);
-        new_self
+    fn add(self, rhs: S) -> Self::Output {
+        check_name_part(rhs.as_ref());
+        self.append_suffix(rhs)
     }
 }
 
-impl Div<String> for &mut PathDSL {
+impl<S> Add<S> for &PathDSL
+where
+    S: NamePart,
+{
     type Output = PathDSL;
 
     #[inline(always)]
-    fn div(self, rhs: String) -> Self::Output {
-        let mut new_self = (*self).clone();
-        new_self.path.push(rhs);
-        new_self
+    fn add(self, rhs: S) -> Self::Output {
+        check_name_part(rhs.as_ref());
+        (*self).clone().append_suffix(rhs)
     }
 }
 
-impl Div<PathBuf> for &mut PathDSL {
+impl<S> Add<S> for &mut PathDSL
+where
+    S: NamePart,
+{
     type Output = PathDSL;
 
     #[inline(always)]
-    fn div(self, rhs: PathBuf) -> Self::Output {
-        let mut new_self = (*self).clone();
-        new_self.path.push(rhs);
-        new_self
+    fn add(self, rhs: S) -> Self::Output {
+        check_name_part(rhs.as_ref());
+        (*self).clone().append_suffix(rhs)
     }
 }
 
-impl Div<Box<Path>> for &mut PathDSL {
+impl<S> Rem<S> for PathDSL
+where
+    S: NamePart,
+{
     type Output = PathDSL;
 
     #[inline(always)]
-    fn div(self, rhs: Box<Path>) -> Self::Output {
-        let mut new_self = (*self).clone();
-        new_self.path.push(rhs);
-        new_self
+    fn rem(self, rhs: S) -> Self::Output {
+        check_name_part(rhs.as_ref());
+        self.replace_extension(rhs)
     }
 }
 
-impl Div<Cow<'_, Path>> for &mut PathDSL {
+impl<S> Rem<S> for &PathDSL
+where
+    S: NamePart,
+{
     type Output = PathDSL;
 
     #[inline(always)]
-    fn div(self, rhs: Cow<'_, Path>) -> Self::Output {
-        let mut new_self = (*self).clone();
-        new_self.path.push(rhs);
-        new_self
+    fn rem(self, rhs: S) -> Self::Output {
+        check_name_part(rhs.as_ref());
+        (*self).clone().replace_extension(rhs)
     }
 }
 
-impl Div<Cow<'_, OsStr>> for &mut PathDSL {
+impl<S> Rem<S> for &mut PathDSL
+where
+    S: NamePart,
+{
     type Output = PathDSL;
 
     #[inline(always)]
-    fn div(self, rhs: Cow<'_, OsStr>) -> Self::Output {
-        let mut new_self = (*self).clone();
-        new_self.path.push(rhs);
-        new_self
+    fn rem(self, rhs: S) -> Self::Output {
+        check_name_part(rhs.as_ref());
+        (*self).clone().replace_extension(rhs)
     }
 }
 
-/////////////////
-// CopylessDSL //
-/////////////////
+///////////////
+// Modifiers //
+///////////////
 
-/// Implementation struct for the no-copy optimization. Should not ever
-/// be found in user code.
-#[derive(Default)]
+/// Segment followed by `. ext`, `+ suffix` or `% ext` in the macros.
+///
+/// The macros can't tell a string from a number, so the operators are resolved by type. With a string
+/// they modify the last component, and with a number `+` and `%` are arithmetic on the segment.
 #[doc(hidden)]
-pub struct CopylessDSL;
+pub struct Modify<S>(pub S);
 
-impl CopylessDSL {
-    /// Creates a new empty CopylessDSL
-    #[doc(hidden)]
+/// Segment whose last component is changed once it has been pushed.
+#[doc(hidden)]
+pub struct Modified<S, P> {
+    segment: S,
+    modify: fn(PathDSL, P) -> PathDSL,
+    part: P,
+}
+
+/// Segment followed by `+` and a number, which is only added once the segment is pushed, so that a
+/// following `% 2` applies to the number alone as it would outside the macros.
+#[doc(hidden)]
+pub struct Sum<S, N>(S, N);
+
+impl<S> Modify<S> {
+    /// `. ext`, which has no operator to overload.
     #[inline(always)]
-    pub fn new() -> CopylessDSL {
-        CopylessDSL
+    pub fn extension<E: Extension>(self, extension: E) -> Modify<Modified<S, E>> {
+        self.modified(PathDSL::append_extension, extension)
     }
-}
 
-impl Into<PathDSL> for CopylessDSL {
     #[inline(always)]
-    fn into(self) -> PathDSL {
-        PathDSL::new()
+    fn modified<P>(self, modify: fn(PathDSL, P) -> PathDSL, part: P) -> Modify<Modified<S, P>> {
+        Modify(Modified {
+            segment: self.0,
+            modify,
+            part,
+        })
     }
 }
 
-impl Into<PathBuf> for CopylessDSL {
+/// Argument of `. ext`, which can only be a string.
+#[doc(hidden)]
+#[diagnostic::on_unimplemented(
+    message = "a path segment ends in `.` followed by `{Self}`, which is not an extension",
+    label = "expected a `&str`, `&OsStr`, `String` or `OsString`",
+    note = "tuple fields past `.15` at the end of a segment must be wrapped in parentheses, like `(wide.16)`"
+)]
+pub trait Extension: NamePart {}
+
+impl<T> Extension for T where T: NamePart {}
+
+/// Integers, which `+` and `%` do arithmetic on instead of modifying the segment.
+#[doc(hidden)]
+pub trait Number {}
+
+impl Number for i8 {}
+impl Number for i16 {}
+impl Number for i32 {}
+impl Number for i64 {}
+impl Number for i128 {}
+impl Number for isize {}
+impl Number for u8 {}
+impl Number for u16 {}
+impl Number for u32 {}
+impl Number for u64 {}
+impl Number for u128 {}
+impl Number for usize {}
+
+// The strings are listed one by one, as a blanket impl over `NamePart` would overlap the one for numbers.
+impl<'a, S> Add<&'a str> for Modify<S> {
+    type Output = Modify<Modified<S, &'a str>>;
+
     #[inline(always)]
-    fn into(self) -> PathBuf {
-        PathBuf::new()
+    fn add(self, rhs: &'a str) -> Self::Output {
+        self.modified(PathDSL::append_suffix, rhs)
     }
 }
 
-impl Div<PathDSL> for CopylessDSL {
-    type Output = PathDSL;
+impl<'a, S> Add<&'a OsStr> for Modify<S> {
+    type Output = Modify<Modified<S, &'a OsStr>>;
 
     #[inline(always)]
-    fn div(self, rhs: PathDSL) -> Self::Output {
-        rhs
+    fn add(self, rhs: &'a OsStr) -> Self::Output {
+        self.modified(PathDSL::append_suffix, rhs)
     }
 }
 
-impl<T> Div<&T> for CopylessDSL
-where
-    T: AsRef<Path> + ?Sized,
-{
-    type Output = PathDSL;
+impl<'a, S> Add<&'a String> for Modify<S> {
+    type Output = Modify<Modified<S, &'a String>>;
 
     #[inline(always)]
-    fn div(self, rhs: &T) -> Self::Output {
-        PathDSL::from(rhs)
+    fn add(self, rhs: &'a String) -> Self::Output {
+        self.modified(PathDSL::append_suffix, rhs)
     }
 }
 
-impl<T> Div<&mut T> for CopylessDSL
-where
-    T: AsRef<Path> + ?Sized,
+impl<'a, S> Add<&'a OsString> for Modify<S> {
+    type Output = Modify<Modified<S, &'a OsString>>;
+
+    #[inline(always)]
+    fn add(self, rhs: &'a OsString) -> Self::Output {
+        self.modified(PathDSL::append_suffix, rhs)
+    }
+}
+
+impl<S> Add<String> for Modify<S> {
+    type Output = Modify<Modified<S, String>>;
+
+    #[inline(always)]
+    fn add(self, rhs: String) -> Self::Output {
+        self.modified(PathDSL::append_suffix, rhs)
+    }
+}
+
+impl<S> Add<OsString> for Modify<S> {
+    type Output = Modify<Modified<S, OsString>>;
+
+    #[inline(always)]
+    fn add(self, rhs: OsString) -> Self::Output {
+        self.modified(PathDSL::append_suffix, rhs)
+    }
+}
+
+impl<S, N> Add<N> for Modify<S>
+where
+    S: Add<N>,
+    N: Number,
 {
-    type Output = PathDSL;
+    type Output = Modify<Sum<S, N>>;
 
     #[inline(always)]
-    fn div(self, rhs: &mut T) -> Self::Output {
-        PathDSL::from(rhs)
+    fn add(self, rhs: N) -> Self::Output {
+        Modify(Sum(self.0, rhs))
     }
 }
 
-impl Div<OsString> for CopylessDSL {
-    type Output = PathDSL;
+impl<'a, S> Rem<&'a str> for Modify<S> {
+    type Output = Modify<Modified<S, &'a str>>;
 
     #[inline(always)]
-    fn div(self, rhs: OsString) -> Self::Output {
-        PathDSL::from(rhs)
+    fn rem(self, rhs: &'a str) -> Self::Output {
+        self.modified(PathDSL::replace_extension, rhs)
     }
 }
 
-impl Div<String> for CopylessDSL {
-    type Output = PathDSL;
+impl<'a, S> Rem<&'a OsStr> for Modify<S> {
+    type Output = Modify<Modified<S, &'a OsStr>>;
 
     #[inline(always)]
-    fn div(self, rhs: String) -> Self::Output {
-        PathDSL::from(rhs)
+    fn rem(self, rhs: &'a OsStr) -> Self::Output {
+        self.modified(PathDSL::replace_extension, rhs)
     }
 }
 
-impl Div<PathBuf> for CopylessDSL {
-    type Output = PathDSL;
+impl<'a, S> Rem<&'a String> for Modify<S> {
+    type Output = Modify<Modified<S, &'a String>>;
 
     #[inline(always)]
-    fn div(self, rhs: PathBuf) -> Self::Output {
-        PathDSL::from(rhs)
+    fn rem(self, rhs: &'a String) -> Self::Output {
+        self.modified(PathDSL::replace_extension, rhs)
     }
 }
 
-impl Div<Box<Path>> for CopylessDSL {
-    type Output = PathDSL;
+impl<'a, S> Rem<&'a OsString> for Modify<S> {
+    type Output = Modify<Modified<S, &'a OsString>>;
 
     #[inline(always)]
-    fn div(self, rhs: Box<Path>) -> Self::Output {
-        PathDSL::from(rhs)
+    fn rem(self, rhs: &'a OsString) -> Self::Output {
+        self.modified(PathDSL::replace_extension, rhs)
     }
 }
 
-impl Div<Cow<'_, Path>> for CopylessDSL {
-    type Output = PathDSL;
+impl<S> Rem<String> for Modify<S> {
+    type Output = Modify<Modified<S, String>>;
+
+    #[inline(always)]
+    fn rem(self, rhs: String) -> Self::Output {
+        self.modified(PathDSL::replace_extension, rhs)
+    }
+}
+
+impl<S> Rem<OsString> for Modify<S> {
+    type Output = Modify<Modified<S, OsString>>;
+
+    #[inline(always)]
+    fn rem(self, rhs: OsString) -> Self::Output {
+        self.modified(PathDSL::replace_extension, rhs)
+    }
+}
+
+impl<S, N> Rem<N> for Modify<S>
+where
+    S: Rem<N>,
+    N: Number,
+{
+    type Output = Modify<S::Output>;
+
+    #[inline(always)]
+    fn rem(self, rhs: N) -> Self::Output {
+        Modify(self.0 % rhs)
+    }
+}
+
+impl<S, N, M> Add<M> for Sum<S, N>
+where
+    S: Add<N>,
+    S::Output: Add<M>,
+{
+    type Output = <S::Output as Add<M>>::Output;
+
+    #[inline(always)]
+    fn add(self, rhs: M) -> Self::Output {
+        self.0 + self.1 + rhs
+    }
+}
+
+impl<S, N, M> Rem<M> for Sum<S, N>
+where
+    N: Rem<M>,
+{
+    type Output = Sum<S, N::Output>;
+
+    #[inline(always)]
+    fn rem(self, rhs: M) -> Self::Output {
+        Sum(self.0, self.1 % rhs)
+    }
+}
+
+impl<S> PathSegment for Modify<S>
+where
+    S: PathSegment,
+{
+    #[inline(always)]
+    fn push_onto(self, path: &mut PathDSL) {
+        self.0.push_onto(path);
+    }
+
+    #[inline(always)]
+    fn donates_buffer(&self) -> bool {
+        self.0.donates_buffer()
+    }
+}
+
+impl<S, P> PathSegment for Modified<S, P>
+where
+    S: PathSegment,
+    P: NamePart,
+{
+    #[inline(always)]
+    fn push_onto(self, path: &mut PathDSL) {
+        check_name_part(self.part.as_ref());
+        self.segment.push_onto(path);
+        *path = (self.modify)(mem::take(path), self.part);
+    }
+
+    #[inline(always)]
+    fn donates_buffer(&self) -> bool {
+        self.segment.donates_buffer()
+    }
+}
+
+impl<S, N> PathSegment for Sum<S, N>
+where
+    S: Add<N>,
+    S::Output: PathSegment,
+{
+    #[inline(always)]
+    fn push_onto(self, path: &mut PathDSL) {
+        (self.0 + self.1).push_onto(path);
+    }
+}
+
+/////////////////
+// CopylessDSL //
+/////////////////
+
+/// Implementation struct for the no-copy optimization. Should not ever
+/// be found in user code.
+#[derive(Default)]
+#[doc(hidden)]
+pub struct CopylessDSL;
+
+impl CopylessDSL {
+    /// Creates a new empty CopylessDSL
+    #[doc(hidden)]
+    #[inline(always)]
+    pub fn new() -> CopylessDSL {
+        CopylessDSL
+    }
+}
+
+impl Into<PathDSL> for CopylessDSL {
+    #[inline(always)]
+    fn into(self) -> PathDSL {
+        PathDSL::new()
+    }
+}
 
+impl Into<PathBuf> for CopylessDSL {
     #[inline(always)]
-    fn div(self, rhs: Cow<'_, Path>) -> Self::Output {
-        PathDSL::from(rhs)
+    fn into(self) -> PathBuf {
+        PathBuf::new()
     }
 }
 
-impl Div<Cow<'_, OsStr>> for CopylessDSL {
+impl<T> Div<T> for CopylessDSL
+where
+    T: PathSegment,
+{
     type Output = PathDSL;
 
     #[inline(always)]
-    fn div(self, rhs: Cow<'_, OsStr>) -> Self::Output {
-        PathDSL::from(&*rhs)
+    fn div(self, rhs: T) -> Self::Output {
+        let mut path = PathDSL::new();
+        rhs.push_onto(&mut path);
+        path
     }
 }
 
-#[cfg(windows)]
+/////////////////
+// Output type //
+/////////////////
+
+/// A path converted by `path!(... => Type)`.
 #[doc(hidden)]
-#[macro_export]
-macro_rules! separator {
-    () => {
-        "\\"
+pub struct Output<T>(pub T);
+
+/// Conversion of a finished path into the result of the macros. Paths without an explicit output type
+/// become a `PathBuf`.
+#[doc(hidden)]
+pub trait IntoOutput {
+    type Output;
+
+    fn into_output(self) -> Self::Output;
+}
+
+impl IntoOutput for PathDSL {
+    type Output = PathBuf;
+
+    #[inline(always)]
+    fn into_output(self) -> PathBuf {
+        self.path
+    }
+}
+
+impl IntoOutput for CopylessDSL {
+    type Output = PathBuf;
+
+    #[inline(always)]
+    fn into_output(self) -> PathBuf {
+        PathBuf::new()
+    }
+}
+
+impl<T> IntoOutput for Output<T> {
+    type Output = T;
+
+    #[inline(always)]
+    fn into_output(self) -> T {
+        self.0
+    }
+}
+
+////////////////
+// Pre-sizing //
+////////////////
+
+/// A segment with the number of bytes it will add to the path, including the separator.
+#[doc(hidden)]
+pub struct Hinted<T> {
+    pub segment: T,
+    pub hint: usize,
+}
+
+/// Wrapper which picks the most specific of `LenHint` and `OptionLenHint` by autoderef, falling back to a
+/// hint of zero. Each is reached at a different level, so a segment whose type is an error still only
+/// has one `path_len_hint`.
+#[doc(hidden)]
+pub struct HintProbe<'a, T: ?Sized>(pub &'a T);
+
+impl<T> Clone for HintProbe<'_, T>
+where
+    T: ?Sized,
+{
+    #[inline(always)]
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for HintProbe<'_, T> where T: ?Sized {}
+
+impl<T> HintProbe<'_, T>
+where
+    T: ?Sized,
+{
+    /// Hint of segments without a known length, which is only reached once the traits don't apply.
+    #[inline(always)]
+    pub fn path_len_hint(self) -> usize {
+        0
+    }
+}
+
+#[doc(hidden)]
+pub trait LenHint {
+    fn path_len_hint(self) -> usize;
+}
+
+impl<T> LenHint for &&HintProbe<'_, T>
+where
+    T: AsRef<Path> + ?Sized,
+{
+    #[inline(always)]
+    fn path_len_hint(self) -> usize {
+        self.0.as_ref().as_os_str().len() + 1
+    }
+}
+
+#[doc(hidden)]
+pub trait OptionLenHint {
+    fn path_len_hint(self) -> usize;
+}
+
+impl<T> OptionLenHint for &HintProbe<'_, Option<T>>
+where
+    T: AsRef<Path>,
+{
+    #[inline(always)]
+    fn path_len_hint(self) -> usize {
+        self.0.as_ref().map_or(0, |segment| segment.as_ref().as_os_str().len() + 1)
+    }
+}
+
+/// Segments pushed by the macros, which are only appended once the hints of all of them are known.
+#[doc(hidden)]
+pub struct Presized<B, T> {
+    base: B,
+    segment: T,
+    hint: usize,
+}
+
+/// Something a `Presized` chain can start from.
+#[doc(hidden)]
+pub trait PresizeBase<T> {
+    /// Appends `segment`, with `additional` bytes reserved for the segments after it.
+    fn build(self, segment: T, hint: usize, additional: usize) -> PathDSL;
+}
+
+impl<T> PresizeBase<T> for CopylessDSL
+where
+    T: PathSegment,
+{
+    #[inline(always)]
+    fn build(self, segment: T, hint: usize, additional: usize) -> PathDSL {
+        if segment.donates_buffer() {
+            // The buffer of the first segment is reused, so only reserve once it is in place.
+            let mut path = self / segment;
+            path.reserve_exact(additional);
+            path
+        } else {
+            // The first segment isn't preceded by a separator, which its hint includes.
+            let mut path = PathDSL::from(PathBuf::with_capacity(hint.saturating_sub(1) + additional));
+            segment.push_onto(&mut path);
+            path
+        }
+    }
+}
+
+impl<T> PresizeBase<T> for PathDSL
+where
+    T: PathSegment,
+{
+    #[inline(always)]
+    fn build(mut self, segment: T, hint: usize, additional: usize) -> PathDSL {
+        if self.capacity() == 0 {
+            // Like `CopylessDSL`, a path without a buffer may be given one by the segment.
+            CopylessDSL.build(segment, hint, additional)
+        } else {
+            self.reserve_exact(hint + additional);
+            self / segment
+        }
+    }
+}
+
+impl<B, U, T> PresizeBase<T> for Presized<B, U>
+where
+    B: PresizeBase<U>,
+    PathDSL: Div<T, Output = PathDSL>,
+{
+    #[inline(always)]
+    fn build(self, segment: T, hint: usize, additional: usize) -> PathDSL {
+        self.base.build(self.segment, self.hint, hint + additional) / segment
+    }
+}
+
+impl<T> Div<Hinted<T>> for CopylessDSL {
+    type Output = Presized<CopylessDSL, T>;
+
+    #[inline(always)]
+    fn div(self, rhs: Hinted<T>) -> Self::Output {
+        Presized {
+            base: self,
+            segment: rhs.segment,
+            hint: rhs.hint,
+        }
+    }
+}
+
+impl<T> Div<Hinted<T>> for PathDSL {
+    type Output = Presized<PathDSL, T>;
+
+    #[inline(always)]
+    fn div(self, rhs: Hinted<T>) -> Self::Output {
+        Presized {
+            base: self,
+            segment: rhs.segment,
+            hint: rhs.hint,
+        }
+    }
+}
+
+impl<B, U, T> Div<Hinted<T>> for Presized<B, U> {
+    type Output = Presized<Presized<B, U>, T>;
+
+    #[inline(always)]
+    fn div(self, rhs: Hinted<T>) -> Self::Output {
+        Presized {
+            base: self,
+            segment: rhs.segment,
+            hint: rhs.hint,
+        }
+    }
+}
+
+impl<B, T> From<Presized<B, T>> for PathDSL
+where
+    B: PresizeBase<T>,
+{
+    #[inline(always)]
+    fn from(path: Presized<B, T>) -> PathDSL {
+        path.base.build(path.segment, path.hint, 0)
+    }
+}
+
+impl<B, T> IntoOutput for Presized<B, T>
+where
+    B: PresizeBase<T>,
+{
+    type Output = PathBuf;
+
+    #[inline(always)]
+    fn into_output(self) -> PathBuf {
+        PathDSL::from(self).path
+    }
+}
+
+///////////////////////
+// In-place building //
+///////////////////////
+
+/// Buffers which [`path_push!`](macro.path_push.html) and [`path_into!`](macro.path_into.html) can
+/// build paths in.
+#[doc(hidden)]
+pub trait PathBuffer {
+    /// Identity, used to get a single `&mut` to the buffer through auto-ref.
+    fn path_buffer(&mut self) -> &mut Self;
+
+    /// Moves the buffer out, leaving an empty one behind.
+    fn take_path(&mut self) -> PathDSL;
+
+    /// Moves a built path back in.
+    fn put_path(&mut self, path: PathDSL);
+}
+
+impl PathBuffer for PathBuf {
+    #[inline(always)]
+    fn path_buffer(&mut self) -> &mut Self {
+        self
+    }
+
+    #[inline(always)]
+    fn take_path(&mut self) -> PathDSL {
+        PathDSL::from(mem::take(self))
+    }
+
+    #[inline(always)]
+    fn put_path(&mut self, path: PathDSL) {
+        *self = path.path;
+    }
+}
+
+impl PathBuffer for PathDSL {
+    #[inline(always)]
+    fn path_buffer(&mut self) -> &mut Self {
+        self
+    }
+
+    #[inline(always)]
+    fn take_path(&mut self) -> PathDSL {
+        mem::take(self)
+    }
+
+    #[inline(always)]
+    fn put_path(&mut self, path: PathDSL) {
+        *self = path;
+    }
+}
+
+/// Base of the segments of `path_push!` and `path_into!`. The buffer is only moved out once every segment
+/// has been evaluated, so a segment which returns early or panics leaves it untouched.
+#[doc(hidden)]
+#[derive(Default)]
+pub struct InPlace;
+
+impl Into<PathDSL> for InPlace {
+    #[inline(always)]
+    fn into(self) -> PathDSL {
+        PathDSL::new()
+    }
+}
+
+impl<T> PresizeBase<T> for InPlace
+where
+    CopylessDSL: PresizeBase<T>,
+{
+    /// Segments which need a `PathDSL` before the end, like splats, are built in a new buffer, which is
+    /// appended to the real one at the end.
+    #[inline(always)]
+    fn build(self, segment: T, hint: usize, additional: usize) -> PathDSL {
+        CopylessDSL.build(segment, hint, additional)
+    }
+}
+
+impl<T> Div<Hinted<T>> for InPlace {
+    type Output = Presized<InPlace, T>;
+
+    #[inline(always)]
+    fn div(self, rhs: Hinted<T>) -> Self::Output {
+        Presized {
+            base: self,
+            segment: rhs.segment,
+            hint: rhs.hint,
+        }
+    }
+}
+
+/// Puts the buffer moved out by `path_push!` and `path_into!` under the segments built on `InPlace`.
+#[doc(hidden)]
+pub trait Rebase {
+    type Output: Into<PathDSL>;
+
+    fn rebase(self, base: PathDSL) -> Self::Output;
+}
+
+impl Rebase for InPlace {
+    type Output = PathDSL;
+
+    #[inline(always)]
+    fn rebase(self, base: PathDSL) -> PathDSL {
+        base
+    }
+}
+
+impl Rebase for PathDSL {
+    type Output = PathDSL;
+
+    #[inline(always)]
+    fn rebase(self, mut base: PathDSL) -> PathDSL {
+        // Pushing an empty path would add a trailing separator.
+        if !self.as_os_str().is_empty() {
+            self.push_onto(&mut base);
+        }
+        base
+    }
+}
+
+impl<B, T> Rebase for Presized<B, T>
+where
+    B: Rebase,
+    B::Output: PresizeBase<T>,
+{
+    type Output = Presized<B::Output, T>;
+
+    #[inline(always)]
+    fn rebase(self, base: PathDSL) -> Self::Output {
+        Presized {
+            base: self.base.rebase(base),
+            segment: self.segment,
+            hint: self.hint,
+        }
+    }
+}
+
+///////////////
+// try_path! //
+///////////////
+
+/// Error produced by [`try_path!`](macro.try_path.html) when one of its segments is `None` or `Err`.
+#[derive(Debug)]
+pub struct PathBuildError {
+    segment: usize,
+    source: Option<Box<dyn Error + Send + Sync>>,
+}
+
+impl PathBuildError {
+    /// Zero-based index of the segment that failed, counting the `|` separated segments of the macro
+    /// invocation, including literals. Segments removed by a false `#[cfg(...)]` are not counted, and
+    /// segments inside `if` blocks are only counted if their branch is taken.
+    #[inline(always)]
+    pub fn segment(&self) -> usize {
+        self.segment
+    }
+
+    /// Returns true if the segment was `None`, false if it was an `Err`.
+    #[inline(always)]
+    pub fn is_none(&self) -> bool {
+        self.source.is_none()
+    }
+
+    /// Consumes the error, returning the error of the failing segment if it was an `Err`.
+    #[inline(always)]
+    pub fn into_source(self) -> Option<Box<dyn Error + Send + Sync>> {
+        self.source
+    }
+}
+
+impl fmt::Display for PathBuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.source {
+            Some(source) => write!(f, "path segment {} failed: {}", self.segment, source),
+            None => write!(f, "path segment {} was None", self.segment),
+        }
+    }
+}
+
+impl Error for PathBuildError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.source {
+            Some(source) => Some(&**source),
+            None => None,
+        }
+    }
+}
+
+/// A fallible segment of a [`try_path!`](macro.try_path.html) invocation.
+///
+/// Implemented for `Option<T>` and `Result<T, E>`. The successful value must be something that can be
+/// on the right hand side of a `/` on a `PathDSL`. Segments which don't implement it can't fail, and are
+/// appended as they are.
+pub trait TrySegment {
+    /// The segment produced on success.
+    type Output;
+
+    /// Converts into the segment, or the error of the segment if there is one.
+    fn into_segment(self) -> Result<Self::Output, Option<Box<dyn Error + Send + Sync>>>;
+}
+
+impl<T> TrySegment for Option<T> {
+    type Output = T;
+
+    #[inline(always)]
+    fn into_segment(self) -> Result<Self::Output, Option<Box<dyn Error + Send + Sync>>> {
+        self.ok_or(None)
+    }
+}
+
+impl<T, E> TrySegment for Result<T, E>
+where
+    E: Into<Box<dyn Error + Send + Sync>>,
+{
+    type Output = T;
+
+    #[inline(always)]
+    fn into_segment(self) -> Result<Self::Output, Option<Box<dyn Error + Send + Sync>>> {
+        self.map_err(|e| Some(e.into()))
+    }
+}
+
+/// Picks how `try_path!` handles a segment by autoref. A [`TrySegment`](trait.TrySegment.html) is
+/// unwrapped, which takes precedence over [`InfallibleKind`](trait.InfallibleKind.html).
+#[doc(hidden)]
+pub trait FallibleKind {
+    #[inline(always)]
+    fn segment_kind(&self) -> Unwrap {
+        Unwrap
+    }
+}
+
+impl<T> FallibleKind for T where T: TrySegment {}
+
+/// Any other segment of `try_path!`, which is appended as it is, like in `path!`.
+#[doc(hidden)]
+pub trait InfallibleKind {
+    #[inline(always)]
+    fn segment_kind(&self) -> PassThrough {
+        PassThrough
+    }
+}
+
+impl<T> InfallibleKind for &T {}
+
+/// Segment of `try_path!` which is unwrapped before it is appended.
+#[doc(hidden)]
+pub struct Unwrap;
+
+impl Unwrap {
+    #[inline(always)]
+    pub fn next<S: TrySegment>(self, counter: &SegmentCounter, segment: S) -> Result<S::Output, PathBuildError> {
+        counter.next(segment)
+    }
+}
+
+/// Segment of `try_path!` which can't fail.
+#[doc(hidden)]
+pub struct PassThrough;
+
+impl PassThrough {
+    #[inline(always)]
+    pub fn next<S>(self, counter: &SegmentCounter, segment: S) -> Result<S, PathBuildError> {
+        Ok(counter.infallible(segment))
+    }
+}
+
+/// Implementation struct keeping track of the current segment in `try_path!`. Should not ever
+/// be found in user code.
+#[derive(Default)]
+#[doc(hidden)]
+pub struct SegmentCounter {
+    segment: Cell<usize>,
+}
+
+impl SegmentCounter {
+    /// Creates a new counter starting at the first segment
+    #[doc(hidden)]
+    #[inline(always)]
+    pub fn new() -> SegmentCounter {
+        SegmentCounter { segment: Cell::new(0) }
+    }
+
+    /// Skips over `count` infallible segments
+    #[doc(hidden)]
+    #[inline(always)]
+    pub fn skip(&self, count: usize) {
+        self.segment.set(self.segment.get() + count);
+    }
+
+    /// Passes through an infallible segment, counting it
+    #[doc(hidden)]
+    #[inline(always)]
+    pub fn infallible<T>(&self, segment: T) -> T {
+        self.skip(1);
+        segment
+    }
+
+    /// Unwraps the next segment, attaching its index to the error
+    #[doc(hidden)]
+    #[inline(always)]
+    pub fn next<S: TrySegment>(&self, segment: S) -> Result<S::Output, PathBuildError> {
+        let index = self.segment.get();
+        self.segment.set(index + 1);
+        segment.into_segment().map_err(|source| PathBuildError { segment: index, source })
+    }
+}
+
+/////////////////
+// Environment //
+/////////////////
+
+/// The environment that variables and home directories are resolved from.
+///
+/// [`ProcessEnv`](struct.ProcessEnv.html) is the environment of the current process. Closures from a
+/// variable name to its value are environments too, which is mostly useful for tests.
+///
+/// ```rust
+/// use path_dsl::EnvContext;
+/// # use std::ffi::OsString;
+/// # use std::path::PathBuf;
+///
+/// let env = |name: &str| match name {
+///     "HOME" | "USERPROFILE" => Some(OsString::from("/home/me")),
+///     _ => None,
+/// };
+/// assert_eq!(env.home_dir(None).unwrap(), PathBuf::from("/home/me"));
+/// ```
+pub trait EnvContext {
+    /// Value of the variable `name`, if it is set.
+    fn var_os(&self, name: &str) -> Option<OsString>;
+
+    /// Home directory of another user, if it can be found. By default, none can.
+    fn user_home(&self, _user: &str) -> Option<PathBuf> {
+        None
+    }
+
+    /// Home directory of `user`, or of the current user if `None`.
+    ///
+    /// The current user's home is `HOME`, or on Windows `USERPROFILE` and then `HOME`. A user named the
+    /// same as `USER` (`USERNAME` on Windows) is the current user; anyone else goes through
+    /// [`user_home`](#method.user_home).
+    fn home_dir(&self, user: Option<&str>) -> Result<PathBuf, UnknownHome> {
+        let current_user = if cfg!(windows) { "USERNAME" } else { "USER" };
+        let is_current = match user {
+            None => true,
+            Some(user) => self.var_os(current_user).as_deref() == Some(OsStr::new(user)),
+        };
+        let home_vars: &[&str] = if cfg!(windows) { &["USERPROFILE", "HOME"] } else { &["HOME"] };
+
+        let home = if is_current {
+            home_vars
+                .iter()
+                .filter_map(|name| self.var_os(name))
+                .find(|home| !home.is_empty())
+                .map(PathBuf::from)
+        } else {
+            None
+        };
+        match (home, user) {
+            (Some(home), _) => Ok(home),
+            (None, Some(user)) => self.user_home(user).ok_or_else(|| UnknownHome {
+                user: Some(user.to_owned()),
+            }),
+            (None, None) => Err(UnknownHome { user: None }),
+        }
+    }
+}
+
+impl<F> EnvContext for F
+where
+    F: Fn(&str) -> Option<OsString>,
+{
+    #[inline(always)]
+    fn var_os(&self, name: &str) -> Option<OsString> {
+        self(name)
+    }
+}
+
+/// The environment of the current process.
+///
+/// On unix-like systems, other users' home directories are looked up in `/etc/passwd`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProcessEnv;
+
+impl EnvContext for ProcessEnv {
+    #[inline(always)]
+    fn var_os(&self, name: &str) -> Option<OsString> {
+        std::env::var_os(name)
+    }
+
+    #[cfg(unix)]
+    fn user_home(&self, user: &str) -> Option<PathBuf> {
+        // name:password:uid:gid:gecos:home:shell
+        let passwd = std::fs::read_to_string("/etc/passwd").ok()?;
+        passwd
+            .lines()
+            .map(|line| line.split(':').collect::<Vec<_>>())
+            .find(|fields| fields.len() >= 6 && fields[0] == user)
+            .map(|fields| PathBuf::from(fields[5]))
+    }
+}
+
+/// Error produced when a home directory can't be found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownHome {
+    user: Option<String>,
+}
+
+impl UnknownHome {
+    /// The user whose home directory was looked for, or `None` for the current user.
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+}
+
+impl fmt::Display for UnknownHome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.user {
+            Some(user) => write!(f, "home directory of user `{}` is unknown", user),
+            None => write!(f, "home directory of the current user is unknown"),
+        }
+    }
+}
+
+impl Error for UnknownHome {}
+
+///////////////
+// env_path! //
+///////////////
+
+/// Error produced by [`expand_env`](fn.expand_env.html) and [`env_path!`](macro.env_path.html) when a
+/// variable without a default is not set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingEnvVar {
+    name: String,
+}
+
+impl MissingEnvVar {
+    /// Name of the variable that is not set.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl fmt::Display for MissingEnvVar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "environment variable `{}` is not set", self.name)
+    }
+}
+
+impl Error for MissingEnvVar {}
+
+/// Expands `$VAR`, `${VAR}` and `${VAR:-default}` in `template` with variables from the environment.
+///
+/// Variables are read with `std::env::var_os`, so don't need to be unicode. A default is used when the
+/// variable is unset or empty, and can contain variables itself. `$$` is a literal `$`, as is a `$` that
+/// isn't followed by a name. A variable that is not set and has no default is an error.
+///
+/// ```rust
+/// use path_dsl::expand_env;
+///
+/// assert_eq!(expand_env("${PATH_DSL_DOC_UNSET:-/tmp}/cache").unwrap(), "/tmp/cache");
+/// assert_eq!(expand_env("$PATH_DSL_DOC_UNSET").unwrap_err().name(), "PATH_DSL_DOC_UNSET");
+/// ```
+pub fn expand_env(template: &str) -> Result<OsString, MissingEnvVar> {
+    expand_env_in(template, &ProcessEnv)
+}
+
+/// Version of [`expand_env`](fn.expand_env.html) which reads variables from `env`.
+///
+/// ```rust
+/// use path_dsl::expand_env_in;
+/// # use std::ffi::OsString;
+///
+/// let env = |name: &str| match name {
+///     "USER" => Some(OsString::from("me")),
+///     _ => None,
+/// };
+/// assert_eq!(expand_env_in("/home/$USER", &env).unwrap(), "/home/me");
+/// ```
+pub fn expand_env_in<E>(template: &str, env: &E) -> Result<OsString, MissingEnvVar>
+where
+    E: EnvContext + ?Sized,
+{
+    let mut expanded = OsString::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('$') {
+        expanded.push(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        if let Some(braced) = rest.strip_prefix('{') {
+            let end = match matching_brace(braced) {
+                Some(end) => end,
+                None => {
+                    expanded.push("${");
+                    rest = braced;
+                    continue;
+                }
+            };
+            let (name, default) = match braced[..end].find(":-") {
+                Some(split) => (&braced[..split], Some(&braced[split + 2..end])),
+                None => (&braced[..end], None),
+            };
+            rest = &braced[end + 1..];
+
+            let value = env.var_os(name).filter(|value| default.is_none() || !value.is_empty());
+            match (value, default) {
+                (Some(value), _) => expanded.push(value),
+                (None, Some(default)) => expanded.push(expand_env_in(default, env)?),
+                (None, None) => return Err(MissingEnvVar { name: name.to_owned() }),
+            }
+        } else {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            if len == 0 || rest.starts_with(|c: char| c.is_ascii_digit()) {
+                // Covers `$$` as well, as the second `$` is skipped over.
+                expanded.push("$");
+                rest = rest.strip_prefix('$').unwrap_or(rest);
+                continue;
+            }
+            let name = &rest[..len];
+            rest = &rest[len..];
+            expanded.push(env.var_os(name).ok_or_else(|| MissingEnvVar { name: name.to_owned() })?);
+        }
+    }
+    expanded.push(rest);
+    Ok(expanded)
+}
+
+fn matching_brace(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+////////////
+// paths! //
+////////////
+
+/// Iterator over every path produced by [`paths!`](macro.paths.html).
+///
+/// Alternatives are evaluated once when the iterator is created, and each path is built from them in a
+/// buffer of exactly the right size. Paths are produced in order, with the last group changing fastest.
+#[derive(Debug, Clone)]
+pub struct Paths {
+    parts: Vec<PathsPart>,
+    indices: Vec<usize>,
+    remaining: usize,
+}
+
+/// One segment of [`Paths`](struct.Paths.html): either fixed, or a group of alternatives.
+#[doc(hidden)]
+#[derive(Debug, Clone)]
+pub enum PathsPart {
+    Fixed(PathBuf),
+    Alternatives(Vec<PathBuf>),
+}
+
+impl Paths {
+    #[doc(hidden)]
+    pub fn new(parts: Vec<PathsPart>) -> Self {
+        let remaining = parts.iter().fold(1_usize, |count, part| match part {
+            PathsPart::Fixed(_) => count,
+            PathsPart::Alternatives(alternatives) => count.saturating_mul(alternatives.len()),
+        });
+        Paths {
+            indices: vec![0; parts.len()],
+            parts,
+            remaining,
+        }
+    }
+}
+
+impl Iterator for Paths {
+    type Item = PathBuf;
+
+    fn next(&mut self) -> Option<PathBuf> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        let segments = self.parts.iter().zip(&self.indices).map(|(part, &index)| match part {
+            PathsPart::Fixed(fixed) => fixed,
+            PathsPart::Alternatives(alternatives) => &alternatives[index],
+        });
+        let capacity = segments.clone().map(|segment| segment.as_os_str().len() + 1).sum();
+        let mut path = PathBuf::with_capacity(capacity);
+        for segment in segments {
+            path.push(segment);
+        }
+
+        // Advance the indices like an odometer, last group first.
+        for (part, index) in self.parts.iter().zip(&mut self.indices).rev() {
+            if let PathsPart::Alternatives(alternatives) = part {
+                *index += 1;
+                if *index < alternatives.len() {
+                    break;
+                }
+                *index = 0;
+            }
+        }
+        Some(path)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl ExactSizeIterator for Paths {}
+
+#[cfg(windows)]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! separator {
+    () => {
+        "\\"
+    };
+}
+
+#[cfg(not(windows))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! separator {
+    () => {
+        "/"
+    };
+}
+
+const fn contains_separator_or_nul(bytes: &[u8]) -> bool {
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'/' || bytes[i] == b'\\' || bytes[i] == 0 {
+            return true;
+        }
+        i += 1;
+    }
+    false
+}
+
+const fn is_reserved_name(bytes: &[u8]) -> bool {
+    const RESERVED: [&[u8]; 4] = [b"CON", b"PRN", b"AUX", b"NUL"];
+    const NUMBERED: [&[u8]; 2] = [b"COM", b"LPT"];
+
+    // Windows reserves device names even with an extension, so only the part before the first dot counts.
+    let mut len = 0;
+    while len < bytes.len() && bytes[len] != b'.' {
+        len += 1;
+    }
+
+    let mut i = 0;
+    while i < RESERVED.len() {
+        if len == 3 && eq_ignore_case(bytes, RESERVED[i]) {
+            return true;
+        }
+        i += 1;
+    }
+    let mut i = 0;
+    while i < NUMBERED.len() {
+        if len == 4 && eq_ignore_case(bytes, NUMBERED[i]) && bytes[3] >= b'1' && bytes[3] <= b'9' {
+            return true;
+        }
+        i += 1;
+    }
+    false
+}
+
+const fn eq_ignore_case(bytes: &[u8], prefix: &[u8]) -> bool {
+    let mut i = 0;
+    while i < prefix.len() {
+        if bytes[i].to_ascii_uppercase() != prefix[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Compile time check of a literal segment in `path!`, given as the `stringify!` of its token and its value.
+/// Panics if the literal isn't a single, portable path component.
+#[doc(hidden)]
+pub const fn check_literal_segment(literal: &str, segment: &str) {
+    let bytes = segment.as_bytes();
+    if contains_separator_or_nul(bytes) {
+        literal_error(
+            "invalid path segment ",
+            literal,
+            ": contains a separator or nul, use `raw \"...\"` if this is intended",
+        );
+    }
+    if !bytes.is_empty() && (bytes[0].is_ascii_whitespace() || bytes[bytes.len() - 1].is_ascii_whitespace()) {
+        literal_error(
+            "invalid path segment ",
+            literal,
+            ": starts or ends with whitespace, use `raw \"...\"` if this is intended",
+        );
+    }
+    if bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+        literal_error(
+            "invalid path segment ",
+            literal,
+            ": starts with a drive, use `C:` or `raw \"...\"` instead",
+        );
+    }
+    if is_reserved_name(bytes) {
+        literal_error(
+            "invalid path segment ",
+            literal,
+            ": is a reserved device name on Windows, use `raw \"...\"` if this is intended",
+        );
+    }
+}
+
+/// Compile time check of a literal extension or suffix in `path!`, given as the `stringify!` of its token
+/// and its value. Panics if it would add a component.
+#[doc(hidden)]
+pub const fn check_literal_part(literal: &str, part: &str) {
+    if contains_separator_or_nul(part.as_bytes()) {
+        literal_error("invalid extension or suffix ", literal, ": contains a separator or nul");
+    }
+}
+
+/// Panics with `what`, `literal` and `reason` joined. Constants can only panic with a single `&str`, so
+/// the message is built in a buffer first, and a literal too long to fit is left out.
+const fn literal_error(what: &str, literal: &str, reason: &str) -> ! {
+    let mut buffer = [0u8; 512];
+    let mut len = push_message(&mut buffer, 0, what);
+    if what.len() + literal.len() + reason.len() <= buffer.len() {
+        len = push_message(&mut buffer, len, literal);
+    } else {
+        len = push_message(&mut buffer, len, "\"...\"");
+    }
+    len = push_message(&mut buffer, len, reason);
+    match core::str::from_utf8(buffer.split_at(len).0) {
+        Ok(message) => panic!("{}", message),
+        Err(_) => panic!("{}", what),
+    }
+}
+
+const fn push_message(buffer: &mut [u8], mut len: usize, part: &str) -> usize {
+    let bytes = part.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() && len < buffer.len() {
+        buffer[len] = bytes[i];
+        len += 1;
+        i += 1;
+    }
+    len
+}
+
+/// Compile time check of a `C:` drive in `path!`, from the `stringify!` of its token. Panics if it isn't a
+/// single ASCII letter.
+#[doc(hidden)]
+pub const fn check_drive_letter(drive: &str) {
+    let bytes = drive.as_bytes();
+    if bytes.len() != 1 || !bytes[0].is_ascii_alphabetic() {
+        panic!("expected a single drive letter before `:`");
+    }
+}
+
+/// Compile time check that an extension or suffix folded into a literal in `path!` is a string, from the
+/// `stringify!` of its token. Numbers after `+` and `%` are arithmetic, which literals can't be folded into.
+#[doc(hidden)]
+pub const fn check_literal_string(token: &str) {
+    let bytes = token.as_bytes();
+    if bytes.is_empty() || !(bytes[0] == b'"' || bytes[0] == b'r') {
+        panic!("extensions and suffixes of literal segments must be strings, wrap arithmetic in parentheses");
+    }
+}
+
+// Common mistakes in `path!`. `compile_error!` can only point at the whole macro call, so they are
+// reported as a bound that no type satisfies on the segment next to the mistake, which points at it.
+
+#[doc(hidden)]
+#[diagnostic::on_unimplemented(
+    message = "use `|` not `/` between path segments",
+    label = "this segment is followed by `/`"
+)]
+pub trait SlashSeparator {}
+
+#[doc(hidden)]
+#[diagnostic::on_unimplemented(
+    message = "use `|` not `,` between path segments",
+    label = "this segment is followed by `,`"
+)]
+pub trait CommaSeparator {}
+
+#[doc(hidden)]
+#[diagnostic::on_unimplemented(
+    message = "use `|` not `;` between path segments",
+    label = "this segment is followed by `;`"
+)]
+pub trait SemicolonSeparator {}
+
+#[doc(hidden)]
+#[diagnostic::on_unimplemented(
+    message = "empty path segment between `||`, use a single `|`",
+    label = "this segment is next to `||`"
+)]
+pub trait DoubleBar {}
+
+#[doc(hidden)]
+#[diagnostic::on_unimplemented(
+    message = "empty path segment before `|`",
+    label = "this segment has two `|` in front of it"
+)]
+pub trait EmptySegment {}
+
+#[doc(hidden)]
+#[diagnostic::on_unimplemented(
+    message = "trailing `|` after the last path segment",
+    label = "this segment is followed by `|` and nothing else"
+)]
+pub trait TrailingBar {}
+
+#[doc(hidden)]
+#[diagnostic::on_unimplemented(
+    message = "missing `|` between path segments",
+    label = "expected `|` before this segment"
+)]
+pub trait MissingBar {}
+
+#[doc(hidden)]
+pub fn slash_separator<T: SlashSeparator>(_: T) -> PathDSL {
+    unreachable!()
+}
+
+#[doc(hidden)]
+pub fn comma_separator<T: CommaSeparator>(_: T) -> PathDSL {
+    unreachable!()
+}
+
+#[doc(hidden)]
+pub fn semicolon_separator<T: SemicolonSeparator>(_: T) -> PathDSL {
+    unreachable!()
+}
+
+#[doc(hidden)]
+pub fn double_bar<T: DoubleBar>(_: T) -> PathDSL {
+    unreachable!()
+}
+
+#[doc(hidden)]
+pub fn empty_segment<T: EmptySegment>(_: T) -> PathDSL {
+    unreachable!()
+}
+
+#[doc(hidden)]
+pub fn trailing_bar<T: TrailingBar>(_: T) -> PathDSL {
+    unreachable!()
+}
+
+#[doc(hidden)]
+pub fn missing_bar<T: MissingBar>(_: T) -> PathDSL {
+    unreachable!()
+}
+
+#[cfg(windows)]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! drive_root {
+    ( $drive:ident ) => {
+        concat!(stringify!($drive), ":\\")
+    };
+    ( $server:literal, $share:literal ) => {
+        concat!("\\\\", $server, "\\", $share, "\\")
+    };
+}
+
+#[cfg(not(windows))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! drive_root {
+    ( $server:literal, $share:literal ) => {
+        concat!("//", $server, "/", $share, "/")
+    };
+}
+
+/// Expands the rest of a path that starts with a `C:` drive, which only exists on Windows.
+#[cfg(windows)]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! drive_path {
+    ( $drive:ident $($path:tt)+ ) => {
+        $crate::path_impl!($($path)+)
+    };
+}
+
+#[cfg(not(windows))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! drive_path {
+    ( $drive:ident $($path:tt)+ ) => {
+        $crate::path_impl!(@error concat!("`", stringify!($drive), ":` drives only exist on Windows, put the segment behind `#[cfg(windows)]`"))
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! concat_separator {
+    ( $e:expr $(, $other:expr)* $(,)? ) => {
+        concat!($e $(, $crate::separator!(), $other)*)
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! path_impl {
+    // State is @[mode](stack)(pending literals)@ remaining input. The mode is empty for `path!` and
+    // `[try 'label counter]` for `try_path!`, and decides how segments are wrapped before they are pushed.
+
+    // Literal runs are gathered into the second group and flushed as one concat_separator! call.
+    // Eight at a time keeps the recursion depth down for very long runs.
+    ( @[$($mode:tt)*]($($stack:expr),*)($($lits:expr),*)@ $lit:literal | $lit2:literal | $lit3:literal | $lit4:literal | $lit5:literal | $lit6:literal | $lit7:literal | $lit8:literal $(| $($other:tt)+)? ) => {
+        $crate::path_impl!( @[$($mode)*]({
+            $crate::path_impl!(@check [$($mode)*] segment $lit);
+            $crate::path_impl!(@check [$($mode)*] segment $lit2);
+            $crate::path_impl!(@check [$($mode)*] segment $lit3);
+            $crate::path_impl!(@check [$($mode)*] segment $lit4);
+            $crate::path_impl!(@check [$($mode)*] segment $lit5);
+            $crate::path_impl!(@check [$($mode)*] segment $lit6);
+            $crate::path_impl!(@check [$($mode)*] segment $lit7);
+            $crate::path_impl!(@check [$($mode)*] segment $lit8);
+            $($stack),*
+        })($($lits,)* $lit, $lit2, $lit3, $lit4, $lit5, $lit6, $lit7, $lit8)@ $($($other)+)? )
+    };
+    ( @[$($mode:tt)*]($($stack:expr),*)($($lits:expr),*)@ $lit:literal $(| $($other:tt)+)? ) => {
+        $crate::path_impl!( @[$($mode)*]({ $crate::path_impl!(@check [$($mode)*] segment $lit); $($stack),* })($($lits,)* $lit)@ $($($other)+)? )
+    };
+    // `raw "literal"` skips the checks, for literals that are meant to be more than one component.
+    ( @[$($mode:tt)*]($($stack:expr),*)($($lits:expr),*)@ raw $lit:literal $(| $($other:tt)+)? ) => {
+        $crate::path_impl!( @[$($mode)*]($($stack),*)($($lits,)* $lit)@ $($($other)+)? )
+    };
+    // `..` and `.` on their own are the parent and current directory.
+    ( @[$($mode:tt)*]($($stack:expr),*)($($lits:expr),*)@ .. $(| $($other:tt)+)? ) => {
+        $crate::path_impl!( @[$($mode)*]($($stack),*)($($lits,)* "..")@ $($($other)+)? )
+    };
+    ( @[$($mode:tt)*]($($stack:expr),*)($($lits:expr),*)@ . $(| $($other:tt)+)? ) => {
+        $crate::path_impl!( @[$($mode)*]($($stack),*)($($lits,)* ".")@ $($($other)+)? )
+    };
+    // Literals with `. "ext"` and `+ "suffix"` modifiers are folded into the run as well.
+    ( @[$($mode:tt)*]($($stack:expr),*)($($lits:expr),*)@ $lit:literal . $($other:tt)+ ) => {
+        $crate::path_impl!( @literal[$($mode)*]({ $crate::path_impl!(@check [$($mode)*] segment $lit); $($stack),* })($($lits),*)($lit)@ . $($other)+ )
+    };
+    ( @[$($mode:tt)*]($($stack:expr),*)($($lits:expr),*)@ $lit:literal + $($other:tt)+ ) => {
+        $crate::path_impl!( @literal[$($mode)*]({ $crate::path_impl!(@check [$($mode)*] segment $lit); $($stack),* })($($lits),*)($lit)@ + $($other)+ )
+    };
+    ( @[$($mode:tt)*]($($stack:expr),*)($($lits:expr),*)@ $lit:literal % $($other:tt)+ ) => {
+        $crate::path_impl!( @literal[$($mode)*]({ $crate::path_impl!(@check [$($mode)*] segment $lit); $($stack),* })($($lits),*)($lit)@ % $($other)+ )
+    };
+    // `#[cfg(predicate)] segment` is resolved by defining the rest of the expansion twice, with and
+    // without the segment, under opposite `#[cfg]`s. Pending literals are carried into both, so runs
+    // on either side of a removed segment are still concatenated.
+    ( @[$($mode:tt)*]($($stack:expr),*)($($lits:expr),*)@ #[cfg($($pred:tt)+)] $($other:tt)+ ) => {
+        $crate::path_impl!( @cfg[$($mode)*]($($stack),*)($($lits),*)($($pred)+)()@ $($other)+ )
+    };
+    // A leading `/`, `C:` drive, or `unc "server" "share"` prefix is joined to a following literal without
+    // a separator. They are only handled with no pending literals, so a root in the middle of a path
+    // replaces what came before it, just like `PathBuf::push`.
+    ( @[$($mode:tt)*]($($stack:expr),*)()@ / $lit:literal $($other:tt)* ) => {
+        $crate::path_impl!( @literal[$($mode)*]({ $crate::path_impl!(@check [$($mode)*] segment $lit); $($stack),* })()($crate::separator!(), $lit)@ $($other)* )
+    };
+    ( @[$($mode:tt)*]($($stack:expr),*)()@ / $(| $($other:tt)+)? ) => {
+        $crate::path_impl!( @[$($mode)*]($($stack),*)($crate::separator!())@ $($($other)+)? )
+    };
+    ( @[$($mode:tt)*]($($stack:expr),*)()@ / $($other:tt)+ ) => {
+        $crate::path_impl!( @[$($mode)*]($($stack),*)($crate::separator!())@ $($other)+ )
+    };
+    ( @[$($mode:tt)*]($($stack:expr),*)()@ $drive:ident : | $lit:literal $($other:tt)* ) => {
+        $crate::drive_path!( $drive @literal[$($mode)*]({ $crate::path_impl!(@check drive $drive); $crate::path_impl!(@check [$($mode)*] segment $lit); $($stack),* })()($crate::drive_root!($drive), $lit)@ $($other)* )
+    };
+    ( @[$($mode:tt)*]($($stack:expr),*)()@ $drive:ident : $(| $($other:tt)+)? ) => {
+        $crate::drive_path!( $drive @[$($mode)*]({ $crate::path_impl!(@check drive $drive); $($stack),* })($crate::drive_root!($drive))@ $($($other)+)? )
+    };
+    ( @[$($mode:tt)*]($($stack:expr),*)()@ unc $server:literal $share:literal | $lit:literal $($other:tt)* ) => {
+        $crate::path_impl!( @literal[$($mode)*]({ $crate::path_impl!(@check [$($mode)*] segment $lit); $($stack),* })()($crate::drive_root!($server, $share), $lit)@ $($other)* )
+    };
+    ( @[$($mode:tt)*]($($stack:expr),*)()@ unc $server:literal $share:literal $(| $($other:tt)+)? ) => {
+        $crate::path_impl!( @[$($mode)*]($($stack),*)($crate::drive_root!($server, $share))@ $($($other)+)? )
+    };
+    // `~` and `~user` are the home directory, from the environment of the process. Finding it can fail, so
+    // they are only accepted by the fallible macros.
+    ( @[]($($stack:expr),*)()@ ~ $($other:tt)* ) => {
+        $crate::path_impl!(@error "`~` home directories may not be found, use `try_path!` or `env_path!` instead of `path!`")
+    };
+    ( @[$($mode:tt)*]($($stack:expr),*)()@ ~ $user:ident $(| $($other:tt)+)? ) => {
+        $crate::path_impl!( @[$($mode)*]($($stack),* / $crate::path_impl!(@hint $crate::path_impl!(@home [$($mode)*] ::std::option::Option::Some(stringify!($user)))))()@ $($($other)+)? )
+    };
+    ( @[$($mode:tt)*]($($stack:expr),*)()@ ~ $(| $($other:tt)+)? ) => {
+        $crate::path_impl!( @[$($mode)*]($($stack),* / $crate::path_impl!(@hint $crate::path_impl!(@home [$($mode)*] ::std::option::Option::None)))()@ $($($other)+)? )
+    };
+    // A trailing `=> Type` converts the finished path. Segments which are matched whole by the arms above
+    // finish the path before converting it, anything else stops collecting at the `=>`.
+    ( @[$($mode:tt)*]($($stack:expr),*)($($lits:expr),*)@ => $ty:ty ) => {
+        $crate::path_impl!( @output ($crate::path_impl!( @[$($mode)*]($($stack),*)($($lits),*)@ )) $ty )
+    };
+    ( @[$($mode:tt)*]($($stack:expr),*)($($lits:expr),*)@ $lit:literal => $ty:ty ) => {
+        $crate::path_impl!( @output ($crate::path_impl!( @[$($mode)*]($($stack),*)($($lits),*)@ $lit )) $ty )
+    };
+    ( @[$($mode:tt)*]($($stack:expr),*)($($lits:expr),*)@ raw $lit:literal => $ty:ty ) => {
+        $crate::path_impl!( @output ($crate::path_impl!( @[$($mode)*]($($stack),*)($($lits),*)@ raw $lit )) $ty )
+    };
+    ( @[$($mode:tt)*]($($stack:expr),*)($($lits:expr),*)@ .. => $ty:ty ) => {
+        $crate::path_impl!( @output ($crate::path_impl!( @[$($mode)*]($($stack),*)($($lits),*)@ .. )) $ty )
+    };
+    ( @[$($mode:tt)*]($($stack:expr),*)($($lits:expr),*)@ . => $ty:ty ) => {
+        $crate::path_impl!( @output ($crate::path_impl!( @[$($mode)*]($($stack),*)($($lits),*)@ . )) $ty )
+    };
+    ( @[$($mode:tt)*]($($stack:expr),*)()@ / => $ty:ty ) => {
+        $crate::path_impl!( @output ($crate::path_impl!( @[$($mode)*]($($stack),*)()@ / )) $ty )
+    };
+    ( @[$($mode:tt)*]($($stack:expr),*)()@ $drive:ident : => $ty:ty ) => {
+        $crate::path_impl!( @output ($crate::path_impl!( @[$($mode)*]($($stack),*)()@ $drive : )) $ty )
+    };
+    ( @[$($mode:tt)*]($($stack:expr),*)()@ unc $server:literal $share:literal => $ty:ty ) => {
+        $crate::path_impl!( @output ($crate::path_impl!( @[$($mode)*]($($stack),*)()@ unc $server $share )) $ty )
+    };
+    ( @[$($mode:tt)*]($($stack:expr),*)()@ ~ $($user:ident)? => $ty:ty ) => {
+        $crate::path_impl!( @output ($crate::path_impl!( @[$($mode)*]($($stack),*)()@ ~ $($user)? )) $ty )
+    };
+    ( @[$($mode:tt)*]($($stack:expr),*)()@ { $fmt:literal $(, $($args:tt)*)? } => $ty:ty ) => {
+        $crate::path_impl!( @output ($crate::path_impl!( @[$($mode)*]($($stack),*)()@ { $fmt $(, $($args)*)? } )) $ty )
+    };
+    ( @[$($mode:tt)*]($($stack:expr),*)()@ ) => {
+        $($stack),*
+    };
+    ( @[$($mode:tt)*]($($stack:expr),*)($($lits:expr),+)@ ) => {
+        $($stack),* / $crate::path_impl!(@hint $crate::path_impl!(@literals [$($mode)*] $($lits),+))
+    };
+    ( @[$($mode:tt)*]($($stack:expr),*)($($lits:expr),+)@ $($other:tt)+ ) => {
+        $crate::path_impl!( @[$($mode)*]($($stack),* / $crate::path_impl!(@hint $crate::path_impl!(@literals [$($mode)*] $($lits),+)))()@ $($other)+ )
+    };
+    ( @[$($mode:tt)*]($($stack:expr),*)()@ ($exp:expr) $(| $($other:tt)+)? ) => {
+        $crate::path_impl!( @[$($mode)*]($($stack),* / $crate::path_impl!(@hint $crate::path_impl!(@wrap [$($mode)*] $exp)))()@ $($($other)+)? )
+    };
+    // `{"format", args...}` segments are formatted straight into the buffer. This must come before blocks.
+    ( @[$($mode:tt)*]($($stack:expr),*)()@ { $fmt:literal $(, $($args:tt)*)? } $(| $($other:tt)+)? ) => {
+        $crate::path_impl!( @[$($mode)*]($($stack),* / $crate::path_impl!(@hint $crate::path_impl!(@infallible [$($mode)*] format_args!($fmt $(, $($args)*)?))))()@ $($($other)+)? )
+    };
+    ( @[$($mode:tt)*]($($stack:expr),*)()@ $blk:block $(| $($other:tt)+)? ) => {
+        $crate::path_impl!( @[$($mode)*]($($stack),* / $crate::path_impl!(@hint $crate::path_impl!(@wrap [$($mode)*] $blk)))()@ $($($other)+)? )
+    };
+    // Plain variables don't need to go through the token-by-token expression collector.
+    // `path` fragments would also match the start of a function call, so only idents get a shortcut.
+    ( @[$($mode:tt)*]($($stack:expr),*)()@ $name:ident $(| $($other:tt)+)? ) => {
+        $crate::path_impl!( @[$($mode)*]($($stack),* / $crate::path_impl!(@hint $crate::path_impl!(@wrap [$($mode)*] $name)))()@ $($($other)+)? )
+    };
+    ( @[$($mode:tt)*]($($stack:expr),*)()@ &$name:ident $(| $($other:tt)+)? ) => {
+        $crate::path_impl!( @[$($mode)*]($($stack),* / $crate::path_impl!(@hint $crate::path_impl!(@wrap [$($mode)*] &$name)))()@ $($($other)+)? )
+    };
+    ( @[$($mode:tt)*]($($stack:expr),*)()@ &mut $name:ident $(| $($other:tt)+)? ) => {
+        $crate::path_impl!( @[$($mode)*]($($stack),* / $crate::path_impl!(@hint $crate::path_impl!(@wrap [$($mode)*] &mut $name)))()@ $($($other)+)? )
+    };
+    // `?opt` segments are skipped when `opt` is `None`.
+    ( @[$($mode:tt)*]($($stack:expr),*)()@ ? $($other:tt)+ ) => {
+        $crate::path_impl!( @collect optional [$($mode)*]($($stack),*)()@ $($other)+ )
+    };
+    // `..iter` splices every item of `iter` into the path.
+    ( @[$($mode:tt)*]($($stack:expr),*)()@ .. $($other:tt)+ ) => {
+        $crate::path_impl!( @collect splat [$($mode)*]($($stack),*)()@ $($other)+ )
+    };
+    // `if cond => segment` and `if cond { segments } else { segments }`. The stack is bound to `path`
+    // once, and each branch runs its segments on top of it.
+    ( @[$($mode:tt)*]($($stack:expr),*)()@ if $($other:tt)+ ) => {
+        $crate::path_impl!( @if[$($mode)*]($($stack),*)(path)()()@ $($other)+ )
+    };
+    // Anything else is an arbitrary expression. `expr` fragments can't be followed by `|`, so collect
+    // tokens until the next top-level `|` and parenthesize them.
+    ( @[$($mode:tt)*]($($stack:expr),*)()@ $first:tt $($other:tt)* ) => {
+        $crate::path_impl!( @collect expr [$($mode)*]($($stack),*)()@ $first $($other)* )
+    };
+    // Common mistakes. None of these tokens can appear at the top level of an expression that is a valid
+    // segment. The error is a bound on the segment next to them, so it points there.
+    ( @collect $kind:tt [$($mode:tt)*]($($stack:expr),*)()@ | $next:literal $($other:tt)* ) => {
+        $crate::empty_segment($next)
+    };
+    ( @collect $kind:tt [$($mode:tt)*]($($stack:expr),*)()@ | $($other:tt)* ) => {
+        $crate::path_impl!(@error "empty path segment before `|`")
+    };
+    ( @collect $kind:tt [$($mode:tt)*]($($stack:expr),*)()@ || $next:literal $($other:tt)* ) => {
+        $crate::double_bar($next)
+    };
+    ( @collect $kind:tt [$($mode:tt)*]($($stack:expr),*)()@ || $($other:tt)* ) => {
+        $crate::path_impl!(@error "empty path segment between `||`, use a single `|`")
+    };
+    ( @collect $kind:tt [$($mode:tt)*]($($stack:expr),*)($seg:literal)@ $next:literal $($other:tt)* ) => {
+        $crate::missing_bar($next)
+    };
+    ( @collect $kind:tt [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)@ | ) => {
+        $crate::trailing_bar($($seg)+)
+    };
+    ( @collect $kind:tt [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)@ || $($other:tt)* ) => {
+        $crate::double_bar($($seg)+)
+    };
+    ( @collect $kind:tt [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)@ / $($other:tt)* ) => {
+        $crate::slash_separator($($seg)+)
+    };
+    ( @collect $kind:tt [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)@ , $($other:tt)* ) => {
+        $crate::comma_separator($($seg)+)
+    };
+    ( @collect $kind:tt [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)@ ; $($other:tt)* ) => {
+        $crate::semicolon_separator($($seg)+)
+    };
+    ( @collect splat [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)@ | $($other:tt)+ ) => {
+        $crate::path_impl!( @[$($mode)*]($crate::path_impl!(@extend ($($stack),*) $crate::path_impl!(@segment splat [$($mode)*] $($seg)+)))()@ $($other)+ )
+    };
+    ( @collect splat [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)@ ) => {
+        $crate::path_impl!(@extend ($($stack),*) $crate::path_impl!(@segment splat [$($mode)*] $($seg)+))
+    };
+    ( @collect [when $path:ident ($($cond:tt)+)] [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)@ $(| $($other:tt)+)? ) => {
+        $crate::path_impl!( @[$($mode)*]({
+            let $path: $crate::PathDSL = ::std::convert::Into::into($($stack),*);
+            if $($cond)+ {
+                ::std::convert::Into::<$crate::PathDSL>::into($crate::path_impl!( @[$($mode)*]($path)()@ $($seg)+ ))
+            } else {
+                $crate::path_impl!(@infallible [$($mode)*] ::std::convert::Into::<$crate::PathDSL>::into($path))
+            }
+        })()@ $($($other)+)? )
+    };
+    ( @collect $kind:ident [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)@ | $($other:tt)+ ) => {
+        $crate::path_impl!( @[$($mode)*]($($stack),* / $crate::path_impl!(@hint $crate::path_impl!(@segment $kind [$($mode)*] $($seg)+)))()@ $($other)+ )
+    };
+    ( @collect $kind:ident [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)@ ) => {
+        $($stack),* / $crate::path_impl!(@hint $crate::path_impl!(@segment $kind [$($mode)*] $($seg)+))
+    };
+    ( @collect expr [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)@ . $arg:tt $($other:tt)* ) => {
+        $crate::path_impl!( @modifiers expr [$($mode)*]($($stack),*)($($seg)+)()@ . $arg $($other)* )
+    };
+    ( @collect expr [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)@ + $arg:tt $($other:tt)* ) => {
+        $crate::path_impl!( @modifiers expr [$($mode)*]($($stack),*)($($seg)+)()@ + $arg $($other)* )
+    };
+    ( @collect expr [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)@ % $arg:tt $($other:tt)* ) => {
+        $crate::path_impl!( @modifiers expr [$($mode)*]($($stack),*)($($seg)+)()@ % $arg $($other)* )
+    };
+    // After an operator that binds looser than `%`, a trailing `+ 1` or `% 2` is no longer applied to the
+    // whole segment, so the rest of it is an expression as written. `.` still binds to the whole segment.
+    ( @collect expr [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)@ - $($other:tt)* ) => {
+        $crate::path_impl!( @collect arith [$($mode)*]($($stack),*)($($seg)+ -)@ $($other)* )
+    };
+    ( @collect expr [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)@ << $($other:tt)* ) => {
+        $crate::path_impl!( @collect arith [$($mode)*]($($stack),*)($($seg)+ <<)@ $($other)* )
+    };
+    ( @collect expr [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)@ >> $($other:tt)* ) => {
+        $crate::path_impl!( @collect arith [$($mode)*]($($stack),*)($($seg)+ >>)@ $($other)* )
+    };
+    ( @collect expr [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)@ & $($other:tt)* ) => {
+        $crate::path_impl!( @collect arith [$($mode)*]($($stack),*)($($seg)+ &)@ $($other)* )
+    };
+    ( @collect expr [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)@ ^ $($other:tt)* ) => {
+        $crate::path_impl!( @collect arith [$($mode)*]($($stack),*)($($seg)+ ^)@ $($other)* )
+    };
+    ( @collect arith [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)@ . $arg:tt $($other:tt)* ) => {
+        $crate::path_impl!( @modifiers arith [$($mode)*]($($stack),*)($($seg)+)()@ . $arg $($other)* )
+    };
+    ( @collect literal [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)@ . $arg:tt $($other:tt)* ) => {
+        $crate::path_impl!( @modifiers literal [$($mode)*]($($stack),*)($($seg)+)()@ . $arg $($other)* )
+    };
+    ( @collect literal [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)@ + $arg:tt $($other:tt)* ) => {
+        $crate::path_impl!( @modifiers literal [$($mode)*]($($stack),*)($($seg)+)()@ + $arg $($other)* )
+    };
+    ( @collect literal [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)@ % $arg:tt $($other:tt)* ) => {
+        $crate::path_impl!( @modifiers literal [$($mode)*]($($stack),*)($($seg)+)()@ % $arg $($other)* )
+    };
+    ( @collect $kind:tt [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)@ => $ty:ty ) => {
+        $crate::path_impl!( @output ($crate::path_impl!( @collect $kind [$($mode)*]($($stack),*)($($seg)+)@ )) $ty )
+    };
+    ( @collect $kind:tt [$($mode:tt)*]($($stack:expr),*)($($seg:tt)*)@ $next:tt $($other:tt)* ) => {
+        $crate::path_impl!( @collect $kind [$($mode)*]($($stack),*)($($seg)* $next)@ $($other)* )
+    };
+
+    // Collected segments of each kind.
+    ( @segment expr [$($mode:tt)*] $($seg:tt)+ ) => {
+        $crate::path_impl!(@wrap [$($mode)*] ($($seg)+))
+    };
+    ( @segment arith [$($mode:tt)*] $($seg:tt)+ ) => {
+        $crate::path_impl!(@wrap [$($mode)*] ($($seg)+))
+    };
+    ( @segment literal [$($mode:tt)*] $($seg:tt)+ ) => {
+        $crate::path_impl!(@infallible [$($mode)*] $($seg)+)
+    };
+    ( @segment splat [$($mode:tt)*] $($seg:tt)+ ) => {
+        $crate::path_impl!(@infallible [$($mode)*] $($seg)+)
+    };
+    ( @segment optional [$($mode:tt)*] $($seg:tt)+ ) => {
+        $crate::path_impl!(@infallible [$($mode)*] {
+            let segment: ::std::option::Option<_> = $($seg)+;
+            segment
+        })
+    };
+
+    ( @cfg[$($mode:tt)*]($($stack:expr),*)($($lits:expr),*)($($pred:tt)+)($($seg:tt)+)@ $(| $($other:tt)+)? ) => {
+        {
+            #[cfg($($pred)+)]
+            macro_rules! path_dsl_cfg {
+                () => {
+                    $crate::path_impl!( @[$($mode)*]($($stack),*)($($lits),*)@ $($seg)+ $(| $($other)+)? )
+                };
+            }
+            #[cfg(not($($pred)+))]
+            macro_rules! path_dsl_cfg {
+                () => {
+                    $crate::path_impl!( @[$($mode)*]($($stack),*)($($lits),*)@ $($($other)+)? )
+                };
+            }
+            path_dsl_cfg!()
+        }
+    };
+    // The `=>` of a conditional segment belongs to the segment.
+    ( @cfg[$($mode:tt)*]($($stack:expr),*)($($lits:expr),*)($($pred:tt)+)(if $($seg:tt)*)@ $next:tt $($other:tt)* ) => {
+        $crate::path_impl!( @cfg[$($mode)*]($($stack),*)($($lits),*)($($pred)+)(if $($seg)* $next)@ $($other)* )
+    };
+    ( @cfg[$($mode:tt)*]($($stack:expr),*)($($lits:expr),*)($($pred:tt)+)($($seg:tt)+)@ => $ty:ty ) => {
+        $crate::path_impl!( @output ($crate::path_impl!( @cfg[$($mode)*]($($stack),*)($($lits),*)($($pred)+)($($seg)+)@ )) $ty )
+    };
+    ( @cfg[$($mode:tt)*]($($stack:expr),*)($($lits:expr),*)($($pred:tt)+)($($seg:tt)*)@ $next:tt $($other:tt)* ) => {
+        $crate::path_impl!( @cfg[$($mode)*]($($stack),*)($($lits),*)($($pred)+)($($seg)* $next)@ $($other)* )
+    };
+
+    // Literal stems are concatenated with their modifiers at compile time, as long as none of them is `%`.
+    ( @literal[$($mode:tt)*]($($stack:expr),*)($($lits:expr),*)($($parts:expr),+)@ . $ext:literal $($other:tt)* ) => {
+        $crate::path_impl!( @literal[$($mode)*]({ $crate::path_impl!(@check string $ext); $crate::path_impl!(@check part $ext); $($stack),* })($($lits),*)($($parts,)+ ".", $ext)@ $($other)* )
+    };
+    ( @literal[$($mode:tt)*]($($stack:expr),*)($($lits:expr),*)($($parts:expr),+)@ + $suffix:literal $($other:tt)* ) => {
+        $crate::path_impl!( @literal[$($mode)*]({ $crate::path_impl!(@check string $suffix); $crate::path_impl!(@check part $suffix); $($stack),* })($($lits),*)($($parts,)+ $suffix)@ $($other)* )
+    };
+    ( @literal[$($mode:tt)*]($($stack:expr),*)($($lits:expr),*)($($parts:expr),+)@ $(| $($other:tt)+)? ) => {
+        $crate::path_impl!( @[$($mode)*]($($stack),*)($($lits,)* concat!($($parts),+))@ $($($other)+)? )
+    };
+    ( @literal[$($mode:tt)*]($($stack:expr),*)($($lits:expr),*)($($parts:expr),+)@ => $ty:ty ) => {
+        $crate::path_impl!( @output ($crate::path_impl!( @literal[$($mode)*]($($stack),*)($($lits),*)($($parts),+)@ )) $ty )
     };
-}
-
-#[cfg(not(windows))]
-#[doc(hidden)]
-#[macro_export]
-macro_rules! separator {
-    () => {
-        "/"
+    ( @literal[$($mode:tt)*]($($stack:expr),*)()($($parts:expr),+)@ $($other:tt)+ ) => {
+        $crate::path_impl!( @collect literal [$($mode)*]($($stack),*)(concat!($($parts),+))@ $($other)+ )
+    };
+    ( @literal[$($mode:tt)*]($($stack:expr),*)($($lits:expr),+)($($parts:expr),+)@ $($other:tt)+ ) => {
+        $crate::path_impl!( @collect literal [$($mode)*]($($stack),* / $crate::path_impl!(@hint $crate::path_impl!(@literals [$($mode)*] $($lits),+)))(concat!($($parts),+))@ $($other)+ )
     };
-}
 
-#[doc(hidden)]
-#[macro_export]
-macro_rules! concat_separator {
-    ( $e:literal, $($other:literal),+ ) => {
-        concat!($e, $crate::separator!(), $crate::concat_separator!($($other),+))
+    // Trailing `. ext`, `+ suffix` and `% ext` modifiers wrap the segment in `Modify`. If anything other
+    // than the end of the segment follows them, they were part of the expression after all, and are put
+    // back as plain tokens (`.len()`). Whether an argument is a string or a number is left to the types,
+    // as macros can't tell them apart.
+    ( @modifiers $kind:ident [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)($($mods:tt)*)@ . $arg:tt $($other:tt)* ) => {
+        $crate::path_impl!( @field $arg
+            (@unmodify $kind [$($mode)*]($($stack),*)($($seg)+)($($mods)* [. $arg])@ $($other)*)
+            (@extension $kind [$($mode)*]($($stack),*)($($seg)+)($($mods)*)($arg)[$arg]@ $($other)*)
+        )
+    };
+    ( @modifiers $kind:ident [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)($($mods:tt)*)@ + & $arg:tt $($other:tt)* ) => {
+        $crate::path_impl!( @modifiers $kind [$($mode)*]($($stack),*)($($seg)+)($($mods)* [+ & $arg])@ $($other)* )
+    };
+    ( @modifiers $kind:ident [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)($($mods:tt)*)@ + $arg:tt $($other:tt)* ) => {
+        $crate::path_impl!( @modifiers $kind [$($mode)*]($($stack),*)($($seg)+)($($mods)* [+ $arg])@ $($other)* )
+    };
+    ( @modifiers $kind:ident [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)($($mods:tt)*)@ % & $arg:tt $($other:tt)* ) => {
+        $crate::path_impl!( @modifiers $kind [$($mode)*]($($stack),*)($($seg)+)($($mods)* [% & $arg])@ $($other)* )
+    };
+    ( @modifiers $kind:ident [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)($($mods:tt)*)@ % $arg:tt $($other:tt)* ) => {
+        $crate::path_impl!( @modifiers $kind [$($mode)*]($($stack),*)($($seg)+)($($mods)* [% $arg])@ $($other)* )
+    };
+    ( @modifiers $kind:ident [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)($($mods:tt)+)@ $(| $($other:tt)+)? ) => {
+        $crate::path_impl!( @[$($mode)*]($($stack),* / $crate::path_impl!(@hint $crate::path_impl!(@modify ($crate::Modify($crate::path_impl!(@segment $kind [$($mode)*] $($seg)+))) $($mods)+)))()@ $($($other)+)? )
+    };
+    ( @modifiers $kind:ident [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)($($mods:tt)+)@ => $ty:ty ) => {
+        $crate::path_impl!( @output ($crate::path_impl!( @modifiers $kind [$($mode)*]($($stack),*)($($seg)+)($($mods)+)@ )) $ty )
+    };
+    ( @modifiers $kind:ident [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)($($mods:tt)*)@ $($other:tt)+ ) => {
+        $crate::path_impl!( @unmodify $kind [$($mode)*]($($stack),*)($($seg)+)($($mods)*)@ $($other)+ )
+    };
+    // A literal or an expression in parentheses after a `.` is an extension, and so is a name after a
+    // literal, which has no fields. A name after anything else is a field or method, so the segment goes on.
+    ( @extension $kind:ident [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)($($mods:tt)*)($check:literal)[$arg:tt]@ $($other:tt)* ) => {
+        $crate::path_impl!( @modifiers $kind [$($mode)*]($($stack),*)($($seg)+)($($mods)* [. $arg])@ $($other)* )
+    };
+    ( @extension $kind:ident [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)($($mods:tt)*)(($($check:tt)*))[$arg:tt]@ $($other:tt)* ) => {
+        $crate::path_impl!( @modifiers $kind [$($mode)*]($($stack),*)($($seg)+)($($mods)* [. $arg])@ $($other)* )
+    };
+    ( @extension literal [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)($($mods:tt)*)($check:ident)[$arg:tt]@ $($other:tt)* ) => {
+        $crate::path_impl!( @modifiers literal [$($mode)*]($($stack),*)($($seg)+)($($mods)* [. $arg])@ $($other)* )
+    };
+    ( @extension $kind:ident [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)($($mods:tt)*)($check:tt)[$arg:tt]@ $($other:tt)* ) => {
+        $crate::path_impl!( @unmodify $kind [$($mode)*]($($stack),*)($($seg)+)($($mods)* [. $arg])@ $($other)* )
+    };
+    // Modifiers put back into the segment. `a + b` is a sum, so a following `% 2` only applies to `b`, and
+    // the rest of the segment is an expression as written.
+    ( @unmodify $kind:ident [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)()@ $($other:tt)* ) => {
+        $crate::path_impl!( @collect $kind [$($mode)*]($($stack),*)($($seg)+)@ $($other)* )
+    };
+    ( @unmodify expr [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)([+ $($arg:tt)+] $($mods:tt)*)@ $($other:tt)* ) => {
+        $crate::path_impl!( @unmodify arith [$($mode)*]($($stack),*)($($seg)+ + $($arg)+)($($mods)*)@ $($other)* )
+    };
+    ( @unmodify $kind:ident [$($mode:tt)*]($($stack:expr),*)($($seg:tt)+)([$($mod:tt)+] $($mods:tt)*)@ $($other:tt)* ) => {
+        $crate::path_impl!( @unmodify $kind [$($mode)*]($($stack),*)($($seg)+ $($mod)+)($($mods)*)@ $($other)* )
+    };
+    // Numbers can't be extensions, so after a `.` they are tuple fields (`pair.0`, or `nested.0.1` which is
+    // lexed as one float) and the segment continues as an expression.
+    ( @field 0 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 1 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 2 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 3 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 4 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 5 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 6 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 7 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 8 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 9 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 10 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 11 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 12 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 13 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 14 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 15 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 0.0 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 0.1 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 0.2 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 0.3 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 1.0 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 1.1 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 1.2 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 1.3 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 2.0 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 2.1 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 2.2 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 2.3 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 3.0 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 3.1 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 3.2 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field 3.3 $field:tt $modifier:tt ) => { $crate::path_impl! $field };
+    ( @field $arg:tt $field:tt $modifier:tt ) => {
+        $crate::path_impl! $modifier
+    };
+    // Literal arguments are checked at compile time, and the rest when the segment is pushed.
+    ( @modify ($segment:expr) ) => {
+        $segment
+    };
+    ( @modify ($segment:expr) [. $ext:literal] $($mods:tt)* ) => {
+        $crate::path_impl!(@modify ({ $crate::path_impl!(@check part $ext); ($segment).extension($ext) }) $($mods)*)
+    };
+    ( @modify ($segment:expr) [+ $suffix:literal] $($mods:tt)* ) => {
+        $crate::path_impl!(@modify ({ $crate::path_impl!(@check part $suffix); ($segment) + $suffix }) $($mods)*)
+    };
+    ( @modify ($segment:expr) [% $ext:literal] $($mods:tt)* ) => {
+        $crate::path_impl!(@modify ({ $crate::path_impl!(@check part $ext); ($segment) % $ext }) $($mods)*)
+    };
+    ( @modify ($segment:expr) [+ & $suffix:tt] $($mods:tt)* ) => {
+        $crate::path_impl!(@modify (($segment) + &$suffix) $($mods)*)
+    };
+    ( @modify ($segment:expr) [% & $ext:tt] $($mods:tt)* ) => {
+        $crate::path_impl!(@modify (($segment) % &$ext) $($mods)*)
+    };
+    ( @modify ($segment:expr) [. $ext:tt] $($mods:tt)* ) => {
+        $crate::path_impl!(@modify (($segment).extension($ext)) $($mods)*)
+    };
+    ( @modify ($segment:expr) [+ $suffix:tt] $($mods:tt)* ) => {
+        $crate::path_impl!(@modify (($segment) + $suffix) $($mods)*)
+    };
+    ( @modify ($segment:expr) [% $ext:tt] $($mods:tt)* ) => {
+        $crate::path_impl!(@modify (($segment) % $ext) $($mods)*)
     };
-    ( $e:literal ) => {
-        $e
-    }
-}
 
-#[doc(hidden)]
-#[macro_export]
-macro_rules! path_impl {
-    ( @($($stack:expr),*)@ ($exp:expr) | $($other:tt)+ ) => {
-        $crate::path_impl!( @($($stack),* / $exp)@ $($other)+ )
+    // Conditions are collected until either `=>` or the block of the first branch.
+    ( @if[$($mode:tt)*]($($stack:expr),*)($path:ident)()($($cond:tt)+)@ => $($other:tt)+ ) => {
+        $crate::path_impl!( @collect [when $path ($($cond)+)] [$($mode)*]($($stack),*)()@ $($other)+ )
+    };
+    ( @if[$($mode:tt)*]($($stack:expr),*)($path:ident)($($chain:tt)*)($($cond:tt)+)@ { $($then:tt)* } $($other:tt)* ) => {
+        $crate::path_impl!( @else[$($mode)*]($($stack),*)($path)($($chain)* if $($cond)+ {
+            ::std::convert::Into::<$crate::PathDSL>::into($crate::path_impl!( @[$($mode)*]($path)()@ $($then)* ))
+        })@ $($other)* )
+    };
+    ( @if[$($mode:tt)*]($($stack:expr),*)($path:ident)($($chain:tt)*)($($cond:tt)*)@ $next:tt $($other:tt)* ) => {
+        $crate::path_impl!( @if[$($mode)*]($($stack),*)($path)($($chain)*)($($cond)* $next)@ $($other)* )
     };
-    ( @($($stack:expr),*)@ ($exp:expr) ) => {
-        $($stack),* / $exp
+    ( @else[$($mode:tt)*]($($stack:expr),*)($path:ident)($($chain:tt)*)@ else if $($other:tt)+ ) => {
+        $crate::path_impl!( @if[$($mode)*]($($stack),*)($path)($($chain)* else)()@ $($other)+ )
     };
-    ( @($($stack:expr),*)@ $blk:block | $($other:tt)+ ) => {
-        $crate::path_impl!( @($($stack),* / $blk)@ $($other)+ )
+    ( @else[$($mode:tt)*]($($stack:expr),*)($path:ident)($($chain:tt)*)@ else { $($else:tt)* } => $ty:ty ) => {
+        $crate::path_impl!( @output ($crate::path_impl!( @else[$($mode)*]($($stack),*)($path)($($chain)*)@ else { $($else)* } )) $ty )
     };
-    ( @($($stack:expr),*)@ $blk:block ) => {
-        $($stack),* / $blk
+    ( @else[$($mode:tt)*]($($stack:expr),*)($path:ident)($($chain:tt)*)@ => $ty:ty ) => {
+        $crate::path_impl!( @output ($crate::path_impl!( @else[$($mode)*]($($stack),*)($path)($($chain)*)@ )) $ty )
     };
-    ( @($($stack:expr),*)@ $name:path | $($other:tt)+ ) => {
-        $crate::path_impl!( @($($stack),* / $name)@ $($other)+ )
+    ( @else[$($mode:tt)*]($($stack:expr),*)($path:ident)($($chain:tt)*)@ else { $($else:tt)* } $(| $($other:tt)+)? ) => {
+        $crate::path_impl!( @[$($mode)*]({
+            let $path: $crate::PathDSL = ::std::convert::Into::into($($stack),*);
+            $($chain)* else {
+                ::std::convert::Into::<$crate::PathDSL>::into($crate::path_impl!( @[$($mode)*]($path)()@ $($else)* ))
+            }
+        })()@ $($($other)+)? )
     };
-    ( @($($stack:expr),*)@ $name:path ) => {
-        $($stack),* / $name
+    ( @else[$($mode:tt)*]($($stack:expr),*)($path:ident)($($chain:tt)*)@ $(| $($other:tt)+)? ) => {
+        $crate::path_impl!( @[$($mode)*]({
+            let $path: $crate::PathDSL = ::std::convert::Into::into($($stack),*);
+            $($chain)* else {
+                ::std::convert::Into::<$crate::PathDSL>::into($path)
+            }
+        })()@ $($($other)+)? )
     };
-    ( @($($stack:expr),*)@ &$name:path | $($other:tt)+ ) => {
-        $crate::path_impl!( @($($stack),* / &$name)@ $($other)+ )
+
+    // Splats go through `Extend`, which needs a `PathDSL` to work on.
+    ( @extend ($($stack:expr),*) $iter:expr ) => {
+        {
+            let mut path: $crate::PathDSL = ::std::convert::Into::into($($stack),*);
+            ::std::iter::Extend::extend(&mut path, $iter);
+            path
+        }
+    };
+
+    // `path_push!` and `path_into!` evaluate every segment first, then move the buffer out, build on it as an
+    // owned `PathDSL`, and move it back.
+    ( @in_place $kind:ident ($buf:expr) $($other:tt)* ) => {
+        {
+            use $crate::PathBuffer as _;
+            let buffer = ($buf).path_buffer();
+            // Matching keeps the temporaries of the segments alive until they are pushed.
+            let path: $crate::PathDSL = match $crate::path_impl!( @[]($crate::InPlace)()@ $($other)* ) {
+                segments => ::std::convert::Into::into($crate::Rebase::rebase(segments, $crate::path_impl!(@take $kind buffer))),
+            };
+            buffer.put_path(path);
+        }
     };
-    ( @($($stack:expr),*)@ &$name:path ) => {
-        $($stack),* / &$name
+    ( @take push $buffer:ident ) => {
+        $buffer.take_path()
     };
-    ( @($($stack:expr),*)@ &mut $name:path | $($other:tt)+ ) => {
-        $crate::path_impl!( @($($stack),* / &mut $name)@ $($other)+ )
+    ( @take into $buffer:ident ) => {
+        {
+            let mut path = $buffer.take_path();
+            path.as_mut_os_string().clear();
+            path
+        }
     };
-    ( @($($stack:expr),*)@ &mut $name:path ) => {
-        $($stack),* / &mut $name
+
+    // Conversion of the finished path for `=> Type`.
+    ( @output ($path:expr) $ty:ty ) => {
+        $crate::Output(::std::convert::Into::<$ty>::into(::std::convert::Into::<$crate::PathDSL>::into($path)))
     };
-    ( @($($stack:expr),*)@ $lit:literal | $lit2:literal | $lit3:literal | $lit4:literal | $lit5:literal | $lit6:literal | $lit7:literal | $lit8:literal | $lit9:literal | $lit10:literal | $lit11:literal | $lit12:literal | $lit13:literal | $lit14:literal | $lit15:literal | $lit16:literal | $($other:tt)+ ) => {
-        $crate::path_impl!( @($($stack),* / $crate::concat_separator!($lit, $lit2, $lit3, $lit4, $lit5, $lit6, $lit7, $lit8, $lit9, $lit10, $lit11, $lit12, $lit13, $lit14, $lit15, $lit16))@ $($other)+ )
+
+    // Segments carry a hint of how much they will grow the path, so the buffer can be reserved once
+    // after the first segment. The hint is found by autoderef dispatch on the concrete segment type.
+    ( @hint $seg:expr ) => {
+        match $seg {
+            segment => {
+                #[allow(unused_imports)]
+                use $crate::{LenHint as _, OptionLenHint as _};
+                let hint = (&&$crate::HintProbe(&segment)).path_len_hint();
+                $crate::Hinted { segment, hint }
+            }
+        }
     };
-    ( @($($stack:expr),*)@ $lit:literal | $lit2:literal | $lit3:literal | $lit4:literal | $lit5:literal | $lit6:literal | $lit7:literal | $lit8:literal | $lit9:literal | $lit10:literal | $lit11:literal | $lit12:literal | $lit13:literal | $lit14:literal | $lit15:literal | $($other:tt)+ ) => {
-        $crate::path_impl!( @($($stack),* / $crate::concat_separator!($lit, $lit2, $lit3, $lit4, $lit5, $lit6, $lit7, $lit8, $lit9, $lit10, $lit11, $lit12, $lit13, $lit14, $lit15))@ $($other)+ )
+
+    // `compile_error!` can only point at the whole macro call, so it is left for mistakes with no segment
+    // next to them to point at, and messages quote the tokens they are about.
+    ( @error $($message:tt)+ ) => {
+        compile_error!($($message)+)
     };
-    ( @($($stack:expr),*)@ $lit:literal | $lit2:literal | $lit3:literal | $lit4:literal | $lit5:literal | $lit6:literal | $lit7:literal | $lit8:literal | $lit9:literal | $lit10:literal | $lit11:literal | $lit12:literal | $lit13:literal | $lit14:literal | $($other:tt)+ ) => {
-        $crate::path_impl!( @($($stack),* / $crate::concat_separator!($lit, $lit2, $lit3, $lit4, $lit5, $lit6, $lit7, $lit8, $lit9, $lit10, $lit11, $lit12, $lit13, $lit14))@ $($other)+ )
+
+    // Per-mode handling of finished segments.
+    ( @wrap [] $seg:expr ) => {
+        $seg
     };
-    ( @($($stack:expr),*)@ $lit:literal | $lit2:literal | $lit3:literal | $lit4:literal | $lit5:literal | $lit6:literal | $lit7:literal | $lit8:literal | $lit9:literal | $lit10:literal | $lit11:literal | $lit12:literal | $lit13:literal | $($other:tt)+ ) => {
-        $crate::path_impl!( @($($stack),* / $crate::concat_separator!($lit, $lit2, $lit3, $lit4, $lit5, $lit6, $lit7, $lit8, $lit9, $lit10, $lit11, $lit12, $lit13))@ $($other)+ )
+    // Segments which aren't `TrySegment`s are passed through, found by autoref like the length hints.
+    ( @wrap [try $label:lifetime $counter:ident] $seg:expr ) => {
+        match $seg {
+            segment => {
+                #[allow(unused_imports)]
+                use $crate::{FallibleKind as _, InfallibleKind as _};
+                match (&segment).segment_kind().next(&$counter, segment) {
+                    ::std::result::Result::Ok(segment) => segment,
+                    ::std::result::Result::Err(error) => break $label ::std::result::Result::Err(error),
+                }
+            }
+        }
     };
-    ( @($($stack:expr),*)@ $lit:literal | $lit2:literal | $lit3:literal | $lit4:literal | $lit5:literal | $lit6:literal | $lit7:literal | $lit8:literal | $lit9:literal | $lit10:literal | $lit11:literal | $lit12:literal | $($other:tt)+ ) => {
-        $crate::path_impl!( @($($stack),* / $crate::concat_separator!($lit, $lit2, $lit3, $lit4, $lit5, $lit6, $lit7, $lit8, $lit9, $lit10, $lit11, $lit12))@ $($other)+ )
+    ( @wrap [env $label:lifetime $counter:ident] $seg:expr ) => {
+        $counter.infallible($seg)
     };
-    ( @($($stack:expr),*)@ $lit:literal | $lit2:literal | $lit3:literal | $lit4:literal | $lit5:literal | $lit6:literal | $lit7:literal | $lit8:literal | $lit9:literal | $lit10:literal | $lit11:literal | $($other:tt)+ ) => {
-        $crate::path_impl!( @($($stack),* / $crate::concat_separator!($lit, $lit2, $lit3, $lit4, $lit5, $lit6, $lit7, $lit8, $lit9, $lit10, $lit11))@ $($other)+ )
+    ( @home [$kind:ident $label:lifetime $counter:ident] $user:expr ) => {
+        $crate::path_impl!(@wrap [try $label $counter] $crate::EnvContext::home_dir(&$crate::ProcessEnv, $user))
     };
-    ( @($($stack:expr),*)@ $lit:literal | $lit2:literal | $lit3:literal | $lit4:literal | $lit5:literal | $lit6:literal | $lit7:literal | $lit8:literal | $lit9:literal | $lit10:literal | $($other:tt)+ ) => {
-        $crate::path_impl!( @($($stack),* / $crate::concat_separator!($lit, $lit2, $lit3, $lit4, $lit5, $lit6, $lit7, $lit8, $lit9, $lit10))@ $($other)+ )
+    ( @infallible [] $seg:expr ) => {
+        $seg
     };
-    ( @($($stack:expr),*)@ $lit:literal | $lit2:literal | $lit3:literal | $lit4:literal | $lit5:literal | $lit6:literal | $lit7:literal | $lit8:literal | $lit9:literal| $($other:tt)+ ) => {
-        $crate::path_impl!( @($($stack),* / $crate::concat_separator!($lit, $lit2, $lit3, $lit4, $lit5, $lit6, $lit7, $lit8, $lit9))@ $($other)+ )
+    ( @infallible [try $label:lifetime $counter:ident] $seg:expr ) => {
+        $counter.infallible($seg)
     };
-    ( @($($stack:expr),*)@ $lit:literal | $lit2:literal | $lit3:literal | $lit4:literal | $lit5:literal | $lit6:literal | $lit7:literal | $lit8:literal | $($other:tt)+ ) => {
-        $crate::path_impl!( @($($stack),* / $crate::concat_separator!($lit, $lit2, $lit3, $lit4, $lit5, $lit6, $lit7, $lit8))@ $($other)+ )
+    ( @infallible [env $label:lifetime $counter:ident] $seg:expr ) => {
+        $counter.infallible($seg)
     };
-    ( @($($stack:expr),*)@ $lit:literal | $lit2:literal | $lit3:literal | $lit4:literal | $lit5:literal | $lit6:literal | $lit7:literal | $($other:tt)+ ) => {
-        $crate::path_impl!( @($($stack),* / $crate::concat_separator!($lit, $lit2, $lit3, $lit4, $lit5, $lit6, $lit7))@ $($other)+ )
+    ( @literals [] $lit:expr ) => {
+        $lit
     };
-    ( @($($stack:expr),*)@ $lit:literal | $lit2:literal | $lit3:literal | $lit4:literal | $lit5:literal | $lit6:literal | $($other:tt)+ ) => {
-        $crate::path_impl!( @($($stack),* / $crate::concat_separator!($lit, $lit2, $lit3, $lit4, $lit5, $lit6))@ $($other)+ )
+    ( @literals [] $($lits:expr),+ ) => {
+        $crate::concat_separator!($($lits),+)
     };
-    ( @($($stack:expr),*)@ $lit:literal | $lit2:literal | $lit3:literal | $lit4:literal | $lit5:literal | $($other:tt)+ ) => {
-        $crate::path_impl!( @($($stack),* / $crate::concat_separator!($lit, $lit2, $lit3, $lit4, $lit5))@ $($other)+ )
+    ( @literals [try $label:lifetime $counter:ident] $($lits:expr),+ ) => {
+        {
+            $counter.skip(0 $(+ $crate::path_impl!(@one $lits))+);
+            $crate::path_impl!(@literals [] $($lits),+)
+        }
     };
-    ( @($($stack:expr),*)@ $lit:literal | $lit2:literal | $lit3:literal | $lit4:literal | $($other:tt)+ ) => {
-        $crate::path_impl!( @($($stack),* / $crate::concat_separator!($lit, $lit2, $lit3, $lit4))@ $($other)+ )
+    // `env_path!` expands literals as templates, separately so errors point at the right one.
+    ( @literals [env $label:lifetime $counter:ident] $($lits:expr),+ ) => {
+        {
+            let mut path = $crate::PathDSL::new();
+            $(path /= $crate::path_impl!(@wrap [try $label $counter] $crate::expand_env($lits));)+
+            path
+        }
     };
-    ( @($($stack:expr),*)@ $lit:literal | $lit2:literal | $lit3:literal | $($other:tt)+ ) => {
-        $crate::path_impl!( @($($stack),* / $crate::concat_separator!($lit, $lit2, $lit3))@ $($other)+ )
+    ( @one $lit:expr ) => {
+        1
     };
-    ( @($($stack:expr),*)@ $lit:literal | $lit2:literal | $($other:tt)+ ) => {
-        $crate::path_impl!( @($($stack),* / $crate::concat_separator!($lit, $lit2))@ $($other)+ )
+    // Literals are checked by const evaluation, which fails to compile if the check panics.
+    ( @check [env $($mode:tt)*] segment $lit:literal ) => {};
+    ( @check [$($mode:tt)*] segment $lit:literal ) => {
+        const _: () = $crate::check_literal_segment(stringify!($lit), concat!($lit));
     };
-    ( @($($stack:expr),*)@ $lit:literal | $($other:tt)+ ) => {
-        $crate::path_impl!( @($($stack),* / $lit)@ $($other)+ )
+    ( @check part $lit:literal ) => {
+        const _: () = $crate::check_literal_part(stringify!($lit), concat!($lit));
     };
-    ( @($($stack:expr),*)@ $lit:literal ) => {
-        $($stack),* / $lit
+    ( @check drive $drive:ident ) => {
+        const _: () = $crate::check_drive_letter(stringify!($drive));
     };
-    ( @($($stack:expr),*)@ ) => {
-        $($stack),*
+    ( @check string $lit:literal ) => {
+        const _: () = $crate::check_literal_string(stringify!($lit));
     };
 }
 
@@ -1322,6 +3932,84 @@ macro_rules! path_impl {
 /// # assert_eq!(ret1, res2);
 /// ```
 ///
+/// ```rust
+/// # use path_dsl::{CopylessDSL, PathDSL, path};
+/// # use std::path::PathBuf;
+/// # struct Config { root: PathBuf }
+/// # let config = Config { root: PathBuf::from("root") };
+/// // Arbitrary expressions are wrapped in parentheses
+/// # let ret1 =
+/// path!(&config.root | "file.txt");
+/// # let res2 =
+/// Into::<PathBuf>::into(CopylessDSL::new() / (&config.root) / "file.txt");
+/// # assert_eq!(ret1, res2);
+/// ```
+///
+/// The expansions above leave out pre-sizing. Each segment is actually wrapped with a hint of how many
+/// bytes it adds, found from its concrete type, and the `/`s only collect the segments. Once the last
+/// segment is known, a buffer for the sum of the hints is allocated once, and the segments are appended
+/// without reallocating. An owned first segment gives the path its buffer instead, and the rest of the
+/// hints are reserved once it is in place. Segments without a known length, like `{"format"}` segments,
+/// have a hint of zero. Conditional and splat segments append everything before them first, so
+/// evaluation order is unchanged.
+///
+/// # Segments
+///
+/// Any expression that can be on the right hand side of a `/` on a `PathDSL` can be used as a segment,
+/// including method calls, field accesses, indexing, function calls, and `?`. Segments are split on
+/// top-level `|`, so expressions that contain a `|` of their own (closures, bitwise or) need to be
+/// wrapped in parentheses.
+///
+/// A segment prefixed with `?` must be an `Option`, and is skipped if it is `None`.
+///
+/// A segment prefixed with `..` is anything that implements `IntoIterator` with items that implement
+/// `AsRef<Path>`. Every item is appended in order using `PathDSL`'s `Extend` implementation.
+///
+/// A segment of the form `if cond => segment` is only appended if `cond` is true. A segment of the form
+/// `if cond { segments } else if cond { segments } else { segments }` appends the segments of the branch
+/// that is taken, and nothing if there is no `else` and no branch is taken. Segments inside branches
+/// follow all the usual rules, including literal concatenation.
+///
+/// A segment can be prefixed with `#[cfg(...)]`, in which case it is removed entirely on targets where
+/// the predicate is false. The literals left on either side are still concatenated.
+///
+/// A few tokens have special meaning as segments. `..` and `.` are the parent and current directory.
+/// A leading `/` is the root of the filesystem, and is directly followed by the first segment. `C:` is
+/// the root of a Windows drive, and `unc "server" "share"` is the root of a Windows network share. Drives
+/// only exist on Windows, so `C:` fails to compile on other targets unless the segment is behind
+/// `#[cfg(windows)]`. Network shares are `//server/share` there. All of these are literals, so take part
+/// in literal concatenation.
+///
+/// `~` and `~user` are home directories, found through [`ProcessEnv`](struct.ProcessEnv.html) when the
+/// path is built. They are not literals. The home directory may not be found, so they are only accepted by
+/// `try_path!` and `env_path!`, which return the error, and `path!` fails to compile on them.
+///
+/// A trailing `=> Type` is recognized wherever a segment can end, and converts the finished path with
+/// `Into<Type>`. The conditional arrow of `if cond => segment` comes first, so it is never mistaken for it.
+///
+/// Literal segments, extensions and suffixes are checked by const evaluation of
+/// `check_literal_segment` and `check_literal_part`, which panic on anything that isn't a single
+/// portable component. `raw "literal"` skips the check. The checks are constants, so they cost
+/// nothing at runtime.
+///
+/// Segments can be followed by modifiers which change their last component. `. "ext"` appends an
+/// extension, `+ "suffix"` appends a raw suffix without a separator, and `% "ext"` replaces the
+/// extension, like `Path::with_extension`. Modifiers can be chained, and are applied in order. Their
+/// argument is a literal, a name, a reference to a name (`+ &suffix`) or any expression in parentheses,
+/// and can be a `&str`, `&OsStr`, `String` or `OsString`. When the segment and every modifier are literals
+/// and there is no `%`, they are folded into the concatenated literal at compile time. Otherwise literal
+/// arguments are still checked at compile time, and the others panic if they contain a separator.
+///
+/// Macros can't tell a string from a number, so the segment is wrapped in `Modify` and the operators are
+/// resolved by type: `+ 1` and `% 2` are integer arithmetic, with the usual precedence. A number after
+/// `.` is a tuple field (`pair.0`) rather than an extension, up to `.15`. A name after `.` is only an
+/// extension after a literal (`"file" . ext`), elsewhere it is a field or method, so a runtime extension
+/// after any other segment is wrapped in parentheses: `stem . (ext)`. After an operator that binds looser
+/// than `%`, like `a - b`, the rest of the segment is an expression as written.
+///
+/// A segment of the form `{"format string", args...}` takes the same arguments as `format!`, and is
+/// formatted directly into the path's buffer without an intermediate `String`.
+///
 /// # String Literal Concatenation
 ///
 /// One of the optimizations made in the macro is the correct concatenation of multiple string literals in a row, as
@@ -1353,11 +4041,12 @@ macro_rules! path_impl {
 /// I then have an internal macro that I define multiple times using `#[cfg(windows)]` etc. to always
 /// give me the correct separator no matter the platform.
 ///
-/// Additionally, due to either my inability to write macros well, or an inherent limitation in rust's
-/// declarative macros, I can't match on a set of `|` separated string literals variadically. As a result
-/// I have unrolled the combiner out to 16 string literals in a row. This should be enough for basically
-/// everyone. If you go above this limit, it will combine them into `ceil(N/16)` literals not 1. If you
-/// need this limit raised, feel free to submit a PR or an issue, but... why? 😃
+/// Rust's declarative macros can't match a set of `|` separated string literals variadically, as the
+/// `|` after the last literal is ambiguous with the rest of the input. Instead the macro gathers literals
+/// one by one (or eight at a time when it can) until it hits something that isn't a literal, then emits
+/// the whole run as a single `&'static str`. There is no limit on the length of a run: any number of
+/// consecutive literals results in exactly one `push`. Extremely long runs may need a higher
+/// `#![recursion_limit]` in the calling crate.
 ///
 /// # CopylessDSL
 ///
@@ -1370,7 +4059,531 @@ macro_rules! path_impl {
 #[macro_export]
 macro_rules! path {
     ( $($other:tt)* ) => {
-         ::std::convert::Into::<std::path::PathBuf>::into($crate::path_impl!( @($crate::CopylessDSL::new())@ $($other)* ));
+         $crate::IntoOutput::into_output($crate::path_impl!( @[]($crate::CopylessDSL::new())()@ $($other)* ))
     };
     () => {  $crate::PathDSL::new() };
 }
+
+/// Fallible version of [`path!`](macro.path.html) for segments that are `Option`s or `Result`s.
+///
+/// Segments which implement [`TrySegment`](trait.TrySegment.html) are unwrapped before being appended,
+/// and any other segment is appended as it is, like in `path!`. The macro evaluates to
+/// `Result<PathBuf, PathBuildError>`; the first segment that is `None` or `Err` stops evaluation of the
+/// rest of the path, and its index is reported in the [`PathBuildError`](struct.PathBuildError.html).
+/// String literals are infallible and are concatenated exactly as in `path!`. A trailing `=> Type`
+/// changes the `Ok` type, like it does for `path!`.
+///
+/// ```rust
+/// use path_dsl::try_path;
+/// # use std::path::PathBuf;
+/// # use std::ffi::OsString;
+///
+/// let home: Option<OsString> = Some(OsString::from("home"));
+/// let cache: Option<PathBuf> = None;
+/// let app = "app";
+///
+/// let config = try_path!(home | ".config" | app).unwrap();
+/// # assert_eq!(config, PathBuf::from("home").join(".config").join("app"));
+///
+/// let err = try_path!("root" | cache | app).unwrap_err();
+/// assert_eq!(err.segment(), 1);
+/// assert!(err.is_none());
+/// ```
+///
+/// The first-argument optimization works as it does for `path!`: if the first segment holds an owning
+/// `PathBuf`, `OsString`, `String` or `PathDSL`, its buffer is reused.
+#[macro_export]
+macro_rules! try_path {
+    ( $($other:tt)* ) => {
+        {
+            #[allow(unused_labels)]
+            let result = 'try_path: {
+                let counter = $crate::SegmentCounter::new();
+                let path = $crate::IntoOutput::into_output(
+                    $crate::path_impl!( @[try 'try_path counter]($crate::CopylessDSL::new())()@ $($other)* ),
+                );
+                ::std::result::Result::Ok::<_, $crate::PathBuildError>(path)
+            };
+            result
+        }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! static_path_impl {
+    // State is @kind(literals to check)(parts)@ remaining input, where kind is `str` or `path`.
+    ( @$kind:ident($($lits:literal),*)($($parts:expr),*)@ $lit:literal $(| $($other:tt)+)? ) => {
+        $crate::static_path_impl!( @$kind($($lits,)* $lit)($($parts,)* $lit)@ $($($other)+)? )
+    };
+    ( @$kind:ident($($lits:literal),*)($($parts:expr),*)@ env!($($args:tt)+) $(| $($other:tt)+)? ) => {
+        $crate::static_path_impl!( @$kind($($lits),*)($($parts,)* env!($($args)+))@ $($($other)+)? )
+    };
+    ( @$kind:ident($($lits:literal),*)($($parts:expr),*)@ .. $(| $($other:tt)+)? ) => {
+        $crate::static_path_impl!( @$kind($($lits),*)($($parts,)* "..")@ $($($other)+)? )
+    };
+    ( @$kind:ident($($lits:literal),*)($($parts:expr),*)@ . $(| $($other:tt)+)? ) => {
+        $crate::static_path_impl!( @$kind($($lits),*)($($parts,)* ".")@ $($($other)+)? )
+    };
+    ( @$kind:ident()()@ / $lit:literal $(| $($other:tt)+)? ) => {
+        $crate::static_path_impl!( @$kind($lit)(concat!($crate::separator!(), $lit))@ $($($other)+)? )
+    };
+    ( @$kind:ident()()@ / $(| $($other:tt)+)? ) => {
+        $crate::static_path_impl!( @$kind()($crate::separator!())@ $($($other)+)? )
+    };
+    ( @str($($lits:literal),*)()@ ) => {
+        ""
+    };
+    ( @str($($lits:literal),*)($($parts:expr),+)@ ) => {
+        $crate::concat_separator!($($parts),+)
+    };
+    ( @path($($lits:literal),*)($($parts:expr),*)@ ) => {
+        {
+            $($crate::path_impl!(@check [] segment $lits);)*
+            ::std::path::Path::new($crate::static_path_impl!( @str()($($parts),*)@ ))
+        }
+    };
+    ( @$kind:ident($($lits:literal),*)($($parts:expr),*)@ $next:tt $($other:tt)* ) => {
+        compile_error!(concat!(
+            "static paths can only contain literals and env! lookups, found `",
+            stringify!($next),
+            "`"
+        ))
+    };
+}
+
+/// All-literal version of [`path!`](macro.path.html) which evaluates to a `&'static Path` without
+/// allocating.
+///
+/// Segments must be string literals or `env!` lookups, as well as `..`, `.` and a leading `/`. Anything
+/// else fails to compile. Literals are checked the same way as in `path!`.
+///
+/// ```rust
+/// use path_dsl::static_path;
+/// # use std::path::{Path, PathBuf};
+///
+/// let config: &'static Path = static_path!(/ "etc" | "app" | "config.toml");
+/// # assert_eq!(config, PathBuf::from(std::path::MAIN_SEPARATOR.to_string()).join("etc").join("app").join("config.toml"));
+/// ```
+///
+/// ```rust,compile_fail
+/// use path_dsl::static_path;
+///
+/// let name = "app";
+/// let config = static_path!("etc" | name);
+/// ```
+#[macro_export]
+macro_rules! static_path {
+    ( $($other:tt)* ) => {
+        $crate::static_path_impl!( @path()()@ $($other)* )
+    };
+}
+
+/// All-literal version of [`path!`](macro.path.html) which evaluates to a `&'static str` literal.
+///
+/// This takes the same segments as [`static_path!`](macro.static_path.html), but expands to a single
+/// `concat!`, so it can be used in `const` items and as the argument to `include_str!` and
+/// `include_bytes!`. Because of that, literals are not checked.
+///
+/// ```rust
+/// use path_dsl::static_path_str;
+///
+/// const CONFIG: &str = static_path_str!("app" | "config.toml");
+/// let manifest = include_str!(static_path_str!(env!("CARGO_MANIFEST_DIR") | "Cargo.toml"));
+/// # assert_eq!(CONFIG, format!("app{}config.toml", std::path::MAIN_SEPARATOR));
+/// # assert!(manifest.contains("[package]"));
+/// ```
+#[macro_export]
+macro_rules! static_path_str {
+    ( $($other:tt)* ) => {
+        $crate::static_path_impl!( @str()()@ $($other)* )
+    };
+}
+
+/// Appends segments to an existing `PathBuf` or `PathDSL` in place.
+///
+/// The first token tree(s) up to the first `|` are the buffer, either a `PathBuf` or `PathDSL` or a
+/// `&mut` to one. The rest are segments, with the same syntax and literal concatenation as
+/// [`path!`](macro.path.html). No new buffer is allocated, the existing one only grows if it runs out of
+/// capacity. Splat and conditional segments are the exception, as they are built in a new buffer
+/// which is appended at the end.
+///
+/// Every segment is evaluated before the buffer is touched, so if one of them returns early with `?` or
+/// panics, the buffer is left unchanged.
+///
+/// ```rust
+/// use path_dsl::path_push;
+/// # use std::path::PathBuf;
+///
+/// let mut buf = PathBuf::from("root");
+/// let name = "file.txt";
+/// path_push!(buf | "dir" | "sub" | name);
+/// # assert_eq!(buf, PathBuf::from("root").join("dir").join("sub").join("file.txt"));
+/// ```
+#[macro_export]
+macro_rules! path_push {
+    ( @buffer($($buf:tt)+) $(| $($other:tt)+)? ) => {
+        $crate::path_impl!( @in_place push ($($buf)+) $($($other)+)? )
+    };
+    ( @buffer($($buf:tt)*) $next:tt $($other:tt)* ) => {
+        $crate::path_push!( @buffer($($buf)* $next) $($other)* )
+    };
+    ( $($other:tt)+ ) => {
+        $crate::path_push!( @buffer() $($other)+ )
+    };
+}
+
+/// Clears an existing `PathBuf` or `PathDSL` and builds a new path in it, keeping its capacity.
+///
+/// The buffer is separated from the segments by a `;`, and the segments have the same syntax as
+/// [`path!`](macro.path.html). This is useful to rebuild many sibling paths in a loop without allocating
+/// for each one. Like `path_push!`, the buffer is left unchanged if a segment returns early or panics.
+///
+/// ```rust
+/// use path_dsl::path_into;
+/// # use std::path::PathBuf;
+///
+/// let root = PathBuf::from("root");
+/// let mut buf = PathBuf::new();
+/// for name in &["a", "b", "c"] {
+///     path_into!(buf; &root | "dir" | name);
+///     // ...
+/// }
+/// # assert_eq!(buf, PathBuf::from("root").join("dir").join("c"));
+/// ```
+#[macro_export]
+macro_rules! path_into {
+    ( $buf:expr ; $($other:tt)* ) => {
+        $crate::path_impl!( @in_place into ($buf) $($other)* )
+    };
+}
+
+/// Version of [`path!`](macro.path.html) which expands environment variables in its string literals.
+///
+/// String literals are templates for [`expand_env`](fn.expand_env.html), so can contain `$VAR`,
+/// `${VAR}` and `${VAR:-default}`. Every other segment works as in `path!`. The macro evaluates to
+/// `Result<PathBuf, PathBuildError>`; the error's index is the literal with the missing variable, and
+/// its source is a [`MissingEnvVar`](struct.MissingEnvVar.html) naming it. Literals are not checked at
+/// compile time, so defaults can contain separators.
+///
+/// ```rust
+/// use path_dsl::{env_path, MissingEnvVar};
+/// # use std::path::PathBuf;
+///
+/// let cache = env_path!("${PATH_DSL_DOC_CACHE:-/tmp/cache}" | "app").unwrap();
+/// # assert_eq!(cache, PathBuf::from("/tmp/cache").join("app"));
+///
+/// let error = env_path!("$PATH_DSL_DOC_MISSING" | "app").unwrap_err();
+/// let missing = error.source().unwrap().downcast_ref::<MissingEnvVar>().unwrap();
+/// assert_eq!(missing.name(), "PATH_DSL_DOC_MISSING");
+/// # use std::error::Error;
+/// ```
+///
+/// For variables known at compile time, [`static_path_str!`](macro.static_path_str.html) takes `env!`
+/// segments and evaluates to a `&'static str`.
+#[macro_export]
+macro_rules! env_path {
+    ( $($other:tt)* ) => {
+        {
+            #[allow(unused_labels)]
+            let result = 'env_path: {
+                let counter = $crate::SegmentCounter::new();
+                let path = $crate::IntoOutput::into_output(
+                    $crate::path_impl!( @[env 'env_path counter]($crate::CopylessDSL::new())()@ $($other)* ),
+                );
+                ::std::result::Result::Ok::<_, $crate::PathBuildError>(path)
+            };
+            result
+        }
+    };
+}
+
+/// Builds every combination of a set of alternatives, like brace expansion in a shell.
+///
+/// Segments are written as in [`path!`](macro.path.html), but a segment in braces is a group of comma
+/// separated alternatives. The macro evaluates to an iterator of `PathBuf`, with one path for every
+/// combination of alternatives, in order, with the last group changing fastest.
+///
+/// ```rust
+/// use path_dsl::paths;
+/// # use std::path::PathBuf;
+///
+/// // src/{lib,main}.rs
+/// let sources: Vec<PathBuf> = paths!("src" | {"lib", "main"} . "rs").collect();
+/// # assert_eq!(sources, vec![PathBuf::from("src").join("lib.rs"), PathBuf::from("src").join("main.rs")]);
+///
+/// // shard_{0..8}/data.bin
+/// let shards = paths!("shard_" + {0..8} | "data.bin");
+/// # assert_eq!(shards.len(), 8);
+/// ```
+///
+/// An alternative is one of:
+///
+/// - a literal or any other expression, which is one alternative.
+/// - an integer range, `start..end` or `start..=end`, which is an alternative for every number in it.
+///   The bounds can be any expressions, such as `{-1..=n + 1}` or `{0..names.len()}`.
+/// - `..iter`, which is an alternative for every item of `iter`.
+///
+/// Modifiers after a group (`. "ext"`, `+ "suffix"` and `% "ext"`) apply to each of its alternatives, and
+/// a literal prefix can be joined to each alternative with `"prefix" + {..}`. The prefix is checked like a
+/// literal segment.
+///
+/// ```rust
+/// use path_dsl::paths;
+/// # use std::path::PathBuf;
+///
+/// let formats = vec!["png", "jpg"];
+/// let images: Vec<PathBuf> = paths!("assets" | {1..=2} + "x" | {"icon", "logo"} | {..&formats}).collect();
+/// assert_eq!(images.len(), 8);
+/// # assert_eq!(images[0], PathBuf::from("assets").join("1x").join("icon").join("png"));
+/// # assert_eq!(images[7], PathBuf::from("assets").join("2x").join("logo").join("jpg"));
+/// ```
+///
+/// Alternatives are evaluated once, when the macro is. Consecutive segments outside of groups are
+/// built with a single `path!` call, so runs of literals between groups are still concatenated at compile
+/// time and checked. Braces always start a group, so `{"format", args}` segments must be written as
+/// `(format!("format", args))`.
+#[macro_export]
+macro_rules! paths {
+    ( $($other:tt)* ) => {
+        $crate::paths_impl!( @split ()()@ $($other)* )
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! paths_impl {
+    // The input is split into segments on top-level `|` first.
+    ( @split ($($segs:tt)*)($($seg:tt)*)@ | $($other:tt)* ) => {
+        $crate::paths_impl!( @split ($($segs)* [$($seg)*])()@ $($other)* )
+    };
+    ( @split ($($segs:tt)*)($($seg:tt)*)@ $next:tt $($other:tt)* ) => {
+        $crate::paths_impl!( @split ($($segs)*)($($seg)* $next)@ $($other)* )
+    };
+    ( @split ($($segs:tt)*)($($seg:tt)*)@ ) => {
+        $crate::paths_impl!( @parts ()()@ $($segs)* [$($seg)*] )
+    };
+
+    // Runs of fixed segments are gathered, `|` separated, and built with one `path!` when a group or
+    // the end is reached.
+    ( @parts ($($parts:tt)*)()@ [{ $($alts:tt)* } $($mods:tt)*] $($other:tt)* ) => {
+        $crate::paths_impl!( @parts ($($parts)* $crate::PathsPart::Alternatives(
+            $crate::paths_impl!( @alternatives [$($mods)*]()()@ $($alts)* )
+        ),)()@ $($other)* )
+    };
+    ( @parts ($($parts:tt)*)()@ [$prefix:literal + { $($alts:tt)* } $($mods:tt)*] $($other:tt)* ) => {
+        $crate::paths_impl!( @parts ($($parts)* $crate::PathsPart::Alternatives({
+            $crate::path_impl!(@check [] segment $prefix);
+            $crate::paths_impl!( @alternatives [$($mods)*]()()@ $($alts)* )
+                .into_iter()
+                .map(|alternative| {
+                    let mut prefixed = ::std::ffi::OsString::from($prefix);
+                    prefixed.push(alternative);
+                    ::std::path::PathBuf::from(prefixed)
+                })
+                .collect()
+        }),)()@ $($other)* )
+    };
+    ( @parts ($($parts:tt)*)(| $($fixed:tt)*)@ [{ $($alts:tt)* } $($mods:tt)*] $($other:tt)* ) => {
+        $crate::paths_impl!( @parts ($($parts)* $crate::PathsPart::Fixed($crate::path!($($fixed)*)),)()@ [{ $($alts)* } $($mods)*] $($other)* )
+    };
+    ( @parts ($($parts:tt)*)(| $($fixed:tt)*)@ [$prefix:literal + { $($alts:tt)* } $($mods:tt)*] $($other:tt)* ) => {
+        $crate::paths_impl!( @parts ($($parts)* $crate::PathsPart::Fixed($crate::path!($($fixed)*)),)()@ [$prefix + { $($alts)* } $($mods)*] $($other)* )
+    };
+    ( @parts ($($parts:tt)*)($($fixed:tt)*)@ [$($seg:tt)*] $($other:tt)* ) => {
+        $crate::paths_impl!( @parts ($($parts)*)($($fixed)* | $($seg)*)@ $($other)* )
+    };
+    ( @parts ($($parts:tt)*)()@ ) => {
+        $crate::Paths::new(::std::vec![$($parts)*])
+    };
+    ( @parts ($($parts:tt)*)(| $($fixed:tt)*)@ ) => {
+        $crate::Paths::new(::std::vec![$($parts)* $crate::PathsPart::Fixed($crate::path!($($fixed)*))])
+    };
+
+    // Alternatives are split on top-level `,`. The modifiers are kept as one token, so they can be
+    // repeated for each alternative.
+    ( @alternatives $mods:tt($($alts:tt)*)($($alt:tt)*)@ , $($other:tt)* ) => {
+        $crate::paths_impl!( @alternatives $mods($($alts)* [$($alt)*])()@ $($other)* )
+    };
+    ( @alternatives $mods:tt($($alts:tt)*)($($alt:tt)*)@ $next:tt $($other:tt)* ) => {
+        $crate::paths_impl!( @alternatives $mods($($alts)*)($($alt)* $next)@ $($other)* )
+    };
+    ( @alternatives $mods:tt($($alts:tt)*)($($alt:tt)+)@ ) => {
+        $crate::paths_impl!( @alternatives $mods($($alts)* [$($alt)+])()@ )
+    };
+    ( @alternatives $mods:tt($([$($alt:tt)*])*)()@ ) => {{
+        let mut alternatives = ::std::vec::Vec::<::std::path::PathBuf>::new();
+        $( $crate::paths_impl!( @alternative alternatives $mods $($alt)* ); )*
+        alternatives
+    }};
+
+    ( @alternative $list:ident [$($mods:tt)*] .. $($iter:tt)+ ) => {
+        $list.extend(::std::iter::IntoIterator::into_iter($($iter)+).map(|item| $crate::path!(item $($mods)*)))
+    };
+    ( @alternative $list:ident $mods:tt $($alt:tt)+ ) => {
+        $crate::paths_impl!( @range $list $mods ()@ $($alt)+ )
+    };
+
+    // Anything before a top-level `..` or `..=` is the start of a range, and anything after it the end.
+    ( @range $list:ident $mods:tt ($($start:tt)+)@ ..= $($end:tt)+ ) => {
+        $crate::paths_impl!( @numbers $list $mods ::std::ops::RangeInclusive::new($($start)+, $($end)+) )
+    };
+    ( @range $list:ident $mods:tt ($($start:tt)+)@ .. $($end:tt)+ ) => {
+        $crate::paths_impl!( @numbers $list $mods ::std::ops::Range { start: $($start)+, end: $($end)+ } )
+    };
+    ( @range $list:ident $mods:tt ($($alt:tt)*)@ $next:tt $($other:tt)* ) => {
+        $crate::paths_impl!( @range $list $mods ($($alt)* $next)@ $($other)* )
+    };
+    ( @range $list:ident [$($mods:tt)*] ($($alt:tt)+)@ ) => {
+        $list.push($crate::path!($($alt)+ $($mods)*))
+    };
+    ( @numbers $list:ident [$($mods:tt)*] $range:expr ) => {
+        $list.extend($range.map(|number| {
+            let number = ::std::string::ToString::to_string(&number);
+            $crate::path!(number $($mods)*)
+        }))
+    };
+}
+
+/// Matches a path against patterns of its components, the inverse of [`path!`](macro.path.html).
+///
+/// Each arm is a list of component patterns in brackets, an optional `if` guard, and an expression. The
+/// path is split with [`Path::iter`](https://doc.rust-lang.org/stable/std/path/struct.Path.html#method.iter)
+/// and matched against each arm in order, and the last arm must be `_`, as a path can have any shape.
+///
+/// ```rust
+/// use path_dsl::{match_path, path};
+///
+/// fn describe(path: &std::path::Path) -> String {
+///     match_path!(path, {
+///         ["src", module, "mod.rs"] => format!("module {:?}", module),
+///         ["tests", .., file: &str] if file.ends_with(".rs") => format!("test {}", file),
+///         [/, "etc", rest @ ..] => format!("{} config components", rest.len()),
+///         _ => String::from("something else"),
+///     })
+/// }
+///
+/// assert_eq!(describe(&path!("src" | "net" | "mod.rs")), "module \"net\"");
+/// assert_eq!(describe(&path!("tests" | "unit" | "paths.rs")), "test paths.rs");
+/// assert_eq!(describe(&path!(/ "etc" | "app" | "config.toml")), "2 config components");
+/// assert_eq!(describe(&path!("README.md")), "something else");
+/// ```
+///
+/// Component patterns use the same vocabulary as `path!` segments where they can:
+///
+/// - A string literal matches a component equal to it, and is checked at compile time in the same way.
+/// - `/` matches the root of the filesystem.
+/// - `name` matches any component and binds it as a `&OsStr`.
+/// - `name: &str` matches any component that is valid unicode and binds it as a `&str`.
+/// - `_` matches any component.
+/// - `..` matches any number of components, and `name @ ..` binds them as a `&[&OsStr]`.
+///
+/// Bindings have their final types in the guard as well as in the expression. Like any `match`,
+/// `break`, `continue` and `return` in an arm apply to the surrounding code.
+///
+/// ```rust,compile_fail
+/// use path_dsl::match_path;
+/// # let path = std::path::Path::new("a");
+///
+/// // match_path! needs a final `_ => ...` arm, as a path can have any shape
+/// let is_a = match_path!(path, { ["a"] => true });
+/// ```
+#[macro_export]
+macro_rules! match_path {
+    ( $path:expr , { $($arms:tt)* } $(,)? ) => {
+        $crate::match_path_impl!( @arms ($path)()@ $($arms)* )
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! match_path_impl {
+    // Arms are turned into slice patterns one at a time. The guard checks literals, and both the guard and
+    // the expression start by rebinding captures to their final types. A capture may only be used by one of
+    // them, so the rebindings don't warn when unused.
+    ( @arms ($path:expr)($($done:tt)*)@ _ => $body:expr $(,)? ) => {
+        match &$path {
+            path => {
+                let components: ::std::vec::Vec<&::std::ffi::OsStr> =
+                    ::std::path::Path::iter(::std::convert::AsRef::<::std::path::Path>::as_ref(path)).collect();
+                match &components[..] {
+                    $($done)*
+                    _ => $body,
+                }
+            }
+        }
+    };
+    ( @arms ($path:expr)($($done:tt)*)@ [ $($elements:tt)* ] $(if $guard:expr)? => $body:block , $($other:tt)* ) => {
+        $crate::match_path_impl!( @elements ($path)($($done)*)[$($other)*]($($guard)?)($body)()()()@ $($elements)* )
+    };
+    ( @arms ($path:expr)($($done:tt)*)@ [ $($elements:tt)* ] $(if $guard:expr)? => $body:block $($other:tt)* ) => {
+        $crate::match_path_impl!( @elements ($path)($($done)*)[$($other)*]($($guard)?)($body)()()()@ $($elements)* )
+    };
+    ( @arms ($path:expr)($($done:tt)*)@ [ $($elements:tt)* ] $(if $guard:expr)? => $body:expr , $($other:tt)* ) => {
+        $crate::match_path_impl!( @elements ($path)($($done)*)[$($other)*]($($guard)?)($body)()()()@ $($elements)* )
+    };
+    ( @arms ($path:expr)($($done:tt)*)@ $($other:tt)* ) => {
+        compile_error!("match_path! needs a final `_ => ...` arm, as a path can have any shape")
+    };
+
+    // State is @elements (path)(done arms)[remaining arms](guard)(body)(pattern)(checks)(bindings)@ elements.
+    ( @elements ($path:expr)($($done:tt)*)[$($other:tt)*]()($body:expr)($($pattern:tt)*)($($checks:tt)*)($($bindings:tt)*)@ ) => {
+        $crate::match_path_impl!( @arms ($path)($($done)*
+            [$($pattern)*] if true $($checks)* => {
+                $($bindings)*
+                $body
+            }
+        )@ $($other)* )
+    };
+    ( @elements ($path:expr)($($done:tt)*)[$($other:tt)*]($guard:expr)($body:expr)($($pattern:tt)*)($($checks:tt)*)($($bindings:tt)*)@ ) => {
+        $crate::match_path_impl!( @arms ($path)($($done)*
+            [$($pattern)*] if true $($checks)* && {
+                $($bindings)*
+                $guard
+            } => {
+                $($bindings)*
+                $body
+            }
+        )@ $($other)* )
+    };
+    ( @elements ($path:expr)($($done:tt)*)[$($other:tt)*]($($guard:expr)?)($body:expr)($($pattern:tt)*)($($checks:tt)*)($($bindings:tt)*)@ / $(, $($elements:tt)*)? ) => {
+        $crate::match_path_impl!( @elements ($path)($($done)*)[$($other)*]($($guard)?)($body)($($pattern)* segment,)(
+            $($checks)* && *segment == ::std::ffi::OsStr::new($crate::separator!())
+        )($($bindings)*)@ $($($elements)*)? )
+    };
+    ( @elements ($path:expr)($($done:tt)*)[$($other:tt)*]($($guard:expr)?)($body:expr)($($pattern:tt)*)($($checks:tt)*)($($bindings:tt)*)@ $lit:literal $(, $($elements:tt)*)? ) => {
+        $crate::match_path_impl!( @elements ($path)($($done)*)[$($other)*]($($guard)?)($body)($($pattern)* segment,)(
+            $($checks)* && *segment == ::std::ffi::OsStr::new($lit)
+        )($($bindings)* $crate::path_impl!(@check [] segment $lit);)@ $($($elements)*)? )
+    };
+    ( @elements ($path:expr)($($done:tt)*)[$($other:tt)*]($($guard:expr)?)($body:expr)($($pattern:tt)*)($($checks:tt)*)($($bindings:tt)*)@ _ $(, $($elements:tt)*)? ) => {
+        $crate::match_path_impl!( @elements ($path)($($done)*)[$($other)*]($($guard)?)($body)($($pattern)* _,)($($checks)*)($($bindings)*)@ $($($elements)*)? )
+    };
+    ( @elements ($path:expr)($($done:tt)*)[$($other:tt)*]($($guard:expr)?)($body:expr)($($pattern:tt)*)($($checks:tt)*)($($bindings:tt)*)@ .. $(, $($elements:tt)*)? ) => {
+        $crate::match_path_impl!( @elements ($path)($($done)*)[$($other)*]($($guard)?)($body)($($pattern)* ..,)($($checks)*)($($bindings)*)@ $($($elements)*)? )
+    };
+    ( @elements ($path:expr)($($done:tt)*)[$($other:tt)*]($($guard:expr)?)($body:expr)($($pattern:tt)*)($($checks:tt)*)($($bindings:tt)*)@ $name:ident @ .. $(, $($elements:tt)*)? ) => {
+        $crate::match_path_impl!( @elements ($path)($($done)*)[$($other)*]($($guard)?)($body)($($pattern)* $name @ ..,)($($checks)*)(
+            $($bindings)* #[allow(unused_variables)] let $name: &[&::std::ffi::OsStr] = $name;
+        )@ $($($elements)*)? )
+    };
+    ( @elements ($path:expr)($($done:tt)*)[$($other:tt)*]($($guard:expr)?)($body:expr)($($pattern:tt)*)($($checks:tt)*)($($bindings:tt)*)@ $name:ident : &str $(, $($elements:tt)*)? ) => {
+        $crate::match_path_impl!( @elements ($path)($($done)*)[$($other)*]($($guard)?)($body)($($pattern)* $name,)(
+            $($checks)* && $name.to_str().is_some()
+        )(
+            $($bindings)* #[allow(unused_variables)] let $name: &str = $name.to_str().unwrap_or_default();
+        )@ $($($elements)*)? )
+    };
+    ( @elements ($path:expr)($($done:tt)*)[$($other:tt)*]($($guard:expr)?)($body:expr)($($pattern:tt)*)($($checks:tt)*)($($bindings:tt)*)@ $name:ident $(, $($elements:tt)*)? ) => {
+        $crate::match_path_impl!( @elements ($path)($($done)*)[$($other)*]($($guard)?)($body)($($pattern)* $name,)($($checks)*)(
+            $($bindings)* #[allow(unused_variables)] let $name: &::std::ffi::OsStr = *$name;
+        )@ $($($elements)*)? )
+    };
+    ( @elements ($path:expr)($($done:tt)*)[$($other:tt)*]($($guard:expr)?)($body:expr)($($pattern:tt)*)($($checks:tt)*)($($bindings:tt)*)@ $next:tt $($elements:tt)* ) => {
+        compile_error!(concat!(
+            "match_path! patterns can only contain literals, `/`, names, `_` and `..`, found `",
+            stringify!($next),
+            "`"
+        ))
+    };
+}
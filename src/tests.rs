@@ -1,16 +1,23 @@
-use crate::{path, PathDSL};
+use crate::{
+    concat_separator, display_segment, env_path, expand_env_in, match_path, path, path_into, path_push, paths, static_path,
+    static_path_str, try_path, CopylessDSL, EnvContext, MissingEnvVar, PathDSL, PathSegment, ProcessEnv,
+};
 use more_asserts::*;
 use std::borrow::Cow;
 use std::ffi::{OsStr, OsString};
-use std::path::{Path, PathBuf};
+use std::fmt;
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
 use std::rc::Rc;
 use std::sync::Arc;
 
 macro_rules! dsl_test {
-    ($(constructor: $constructor:path,)? $(converter: ($($conv:tt)+),)? $(self: ($($selfmod:tt)+),)? name: $id:ident) => {
+    ($(constructor: $constructor:path,)? $(converter: ($($conv:tt)+),)? $(self: ($($selfmod:tt)+),)? $(expected: $expected:literal,)? name: $id:ident) => {
         #[allow(unused)]
         #[test]
         fn $id() {
+            let expected = "ident";
+            $(let expected = $expected;)?
+
             let mut first = $($constructor)?("ident");
             let second = $($($conv)+)?(first);
             let res_dsl = $($($selfmod)+)?PathDSL::new() / second / "my_file";
@@ -19,29 +26,49 @@ macro_rules! dsl_test {
             let second = $($($conv)+)?(first);
             let res_macro = path!(second | "my_file");
 
+            let mut first = $($constructor)?("ident");
+            let second = $($($conv)+)?(first);
+            let mut res_div_assign = PathDSL::new();
+            res_div_assign /= second;
+            res_div_assign /= "my_file";
+
+            let mut first = $($constructor)?("ident");
+            let second = $($($conv)+)?(first);
+            let mut res_bitor_assign = PathDSL::new();
+            res_bitor_assign |= second;
+            res_bitor_assign |= "my_file";
+
             let mut real = PathBuf::new();
-            real.push("ident");
+            real.push(expected);
             real.push("my_file");
 
             assert_eq!(res_dsl, real);
             assert_eq!(res_macro, real);
+            assert_eq!(res_div_assign, real);
+            assert_eq!(res_bitor_assign, real);
         }
     };
 }
 
 macro_rules! owned_dsl_test {
-    ($(constructor: $constructor:path,)? name: $name:ident) => {
-        paste::item!(dsl_test!{$(constructor: $constructor,)? name: [<dsl_ $name>]});
-        paste::item!(dsl_test!{$(constructor: $constructor,)? converter: (&), name: [<dsl_ $name _ref>]});
-        paste::item!(dsl_test!{$(constructor: $constructor,)? converter: (&mut), name: [<dsl_ $name _ref_mut>]});
+    // For types whose references aren't `AsRef<Path>`, which can only be used by value.
+    (by_value, $(constructor: $constructor:path,)? $(expected: $expected:literal,)? name: $name:ident) => {
+        paste::item!(dsl_test!{$(constructor: $constructor,)? $(expected: $expected,)? name: [<dsl_ $name>]});
+        paste::item!(dsl_test!{$(constructor: $constructor,)? self: (&), $(expected: $expected,)? name: [<dsl_ref_ $name>]});
+        paste::item!(dsl_test!{$(constructor: $constructor,)? self: (&mut), $(expected: $expected,)? name: [<dsl_ref_mut_ $name>]});
+    };
+    ($(constructor: $constructor:path,)? $(expected: $expected:literal,)? name: $name:ident) => {
+        paste::item!(dsl_test!{$(constructor: $constructor,)? $(expected: $expected,)? name: [<dsl_ $name>]});
+        paste::item!(dsl_test!{$(constructor: $constructor,)? converter: (&), $(expected: $expected,)? name: [<dsl_ $name _ref>]});
+        paste::item!(dsl_test!{$(constructor: $constructor,)? converter: (&mut), $(expected: $expected,)? name: [<dsl_ $name _ref_mut>]});
 
-        paste::item!(dsl_test!{$(constructor: $constructor,)? self: (&), name: [<dsl_ref_ $name>]});
-        paste::item!(dsl_test!{$(constructor: $constructor,)? converter: (&), self: (&), name: [<dsl_ref_ $name _ref>]});
-        paste::item!(dsl_test!{$(constructor: $constructor,)? converter: (&mut), self: (&), name: [<dsl_ref_ $name _ref_mut>]});
+        paste::item!(dsl_test!{$(constructor: $constructor,)? self: (&), $(expected: $expected,)? name: [<dsl_ref_ $name>]});
+        paste::item!(dsl_test!{$(constructor: $constructor,)? converter: (&), self: (&), $(expected: $expected,)? name: [<dsl_ref_ $name _ref>]});
+        paste::item!(dsl_test!{$(constructor: $constructor,)? converter: (&mut), self: (&), $(expected: $expected,)? name: [<dsl_ref_ $name _ref_mut>]});
 
-        paste::item!(dsl_test!{$(constructor: $constructor,)? self: (&mut), name: [<dsl_ref_mut_ $name>]});
-        paste::item!(dsl_test!{$(constructor: $constructor,)? converter: (&), self: (&mut), name: [<dsl_ref_mut_ $name _ref>]});
-        paste::item!(dsl_test!{$(constructor: $constructor,)? converter: (&mut), self: (&mut), name: [<dsl_ref_mut_ $name _ref_mut>]});
+        paste::item!(dsl_test!{$(constructor: $constructor,)? self: (&mut), $(expected: $expected,)? name: [<dsl_ref_mut_ $name>]});
+        paste::item!(dsl_test!{$(constructor: $constructor,)? converter: (&), self: (&mut), $(expected: $expected,)? name: [<dsl_ref_mut_ $name _ref>]});
+        paste::item!(dsl_test!{$(constructor: $constructor,)? converter: (&mut), self: (&mut), $(expected: $expected,)? name: [<dsl_ref_mut_ $name _ref_mut>]});
     };
 }
 
@@ -54,7 +81,12 @@ owned_dsl_test!(constructor: PathBuf::from, name: pathbuf);
 owned_dsl_test!(constructor: PathDSL::from, name: dsl);
 owned_dsl_test!(constructor: gen_box_path, name: box_path);
 owned_dsl_test!(constructor: gen_cow_path, name: cow_path);
-owned_dsl_test!(constructor: gen_cow_osstr, name: arc_osstr);
+owned_dsl_test!(constructor: gen_cow_osstr, name: cow_osstr);
+owned_dsl_test!(by_value, constructor: gen_box_osstr, name: box_osstr);
+owned_dsl_test!(constructor: gen_rc_path, name: rc_path);
+owned_dsl_test!(constructor: gen_arc_path, name: arc_path);
+owned_dsl_test!(by_value, constructor: gen_arc_osstr, name: arc_osstr);
+owned_dsl_test!(by_value, constructor: gen_char, expected: "i", name: char);
 
 fn gen_box_path(p: &str) -> Box<Path> {
     Box::from(Path::new(p))
@@ -65,6 +97,21 @@ fn gen_cow_path(p: &str) -> Cow<'_, Path> {
 fn gen_cow_osstr(p: &str) -> Cow<'_, OsStr> {
     Cow::from(OsStr::new(p))
 }
+fn gen_box_osstr(p: &str) -> Box<OsStr> {
+    Box::from(OsStr::new(p))
+}
+fn gen_rc_path(p: &str) -> Rc<Path> {
+    Rc::from(Path::new(p))
+}
+fn gen_arc_path(p: &str) -> Arc<Path> {
+    Arc::from(Path::new(p))
+}
+fn gen_arc_osstr(p: &str) -> Arc<OsStr> {
+    Arc::from(OsStr::new(p))
+}
+fn gen_char(p: &str) -> char {
+    p.chars().next().unwrap()
+}
 
 macro_rules! partial_ord_test {
     (owned, $lhs:expr, $rhs:expr) => {
@@ -143,3 +190,1160 @@ into_test!(type: Arc<Path>, name: arc_path);
 into_test!(type: Rc<Path>, name: rc_path);
 into_test!(type: Cow<'_, Path>, converter: (&), name: cow_path);
 into_test!(type: Cow<'_, OsStr>, converter: (&), name: cow_osstr);
+
+macro_rules! literal_run_test {
+    (name: $name:ident, $($lit:literal)|+) => {
+        #[test]
+        fn $name() {
+            let res_macro = path!($($lit)|+);
+            let res_expanded = Into::<PathBuf>::into(CopylessDSL::new() / concat_separator!($($lit),+));
+
+            let mut real = PathBuf::new();
+            $(real.push($lit);)+
+
+            assert_eq!(res_macro, real);
+            assert_eq!(res_expanded, real);
+            // A single push from a single &'static str allocates exactly once.
+            assert_eq!(res_macro.capacity(), res_macro.as_os_str().len());
+        }
+    };
+}
+
+literal_run_test!(name: literal_run_17, "0" | "1" | "2" | "3" | "4" | "5" | "6" | "7" | "8" | "9" | "10" | "11" | "12" | "13" | "14" | "15" | "16");
+literal_run_test!(
+    name: literal_run_32,
+    "0" | "1" | "2" | "3" | "4" | "5" | "6" | "7" | "8" | "9" | "10" | "11" | "12" | "13" | "14" | "15" |
+    "16" | "17" | "18" | "19" | "20" | "21" | "22" | "23" | "24" | "25" | "26" | "27" | "28" | "29" | "30" | "31"
+);
+literal_run_test!(
+    name: literal_run_100,
+    "0" | "1" | "2" | "3" | "4" | "5" | "6" | "7" | "8" | "9" | "10" | "11" | "12" | "13" | "14" | "15" |
+    "16" | "17" | "18" | "19" | "20" | "21" | "22" | "23" | "24" | "25" | "26" | "27" | "28" | "29" | "30" | "31" |
+    "32" | "33" | "34" | "35" | "36" | "37" | "38" | "39" | "40" | "41" | "42" | "43" | "44" | "45" | "46" | "47" |
+    "48" | "49" | "50" | "51" | "52" | "53" | "54" | "55" | "56" | "57" | "58" | "59" | "60" | "61" | "62" | "63" |
+    "64" | "65" | "66" | "67" | "68" | "69" | "70" | "71" | "72" | "73" | "74" | "75" | "76" | "77" | "78" | "79" |
+    "80" | "81" | "82" | "83" | "84" | "85" | "86" | "87" | "88" | "89" | "90" | "91" | "92" | "93" | "94" | "95" |
+    "96" | "97" | "98" | "99"
+);
+
+struct Fields {
+    root: PathBuf,
+    name: String,
+}
+
+impl Fields {
+    fn new() -> Self {
+        Fields {
+            root: PathBuf::from("root"),
+            name: String::from("name"),
+        }
+    }
+
+    fn root(&self) -> &Path {
+        &self.root
+    }
+}
+
+fn segment() -> String {
+    String::from("segment")
+}
+
+fn fallible_segment(fail: bool) -> Result<&'static str, ()> {
+    if fail {
+        Err(())
+    } else {
+        Ok("fallible")
+    }
+}
+
+#[test]
+fn expr_method_call() {
+    let f = Fields::new();
+    let p = path!(f.root() | "a" | f.name.as_str());
+    assert_eq!(p, Path::new("root").join("a").join("name"));
+}
+
+#[test]
+fn expr_field_access() {
+    let f = Fields::new();
+    let p = path!(&f.root | "a" | &f.name);
+    assert_eq!(p, Path::new("root").join("a").join("name"));
+    // Moves out of the field, stealing its buffer
+    let p = path!(f.root | "a");
+    assert_eq!(p, Path::new("root").join("a"));
+}
+
+#[test]
+fn expr_indexing() {
+    let args = [String::from("zero"), String::from("one")];
+    let p = path!(&args[1] | "a" | &args[0]);
+    assert_eq!(p, Path::new("one").join("a").join("zero"));
+}
+
+#[test]
+fn expr_function_call() {
+    let p = path!(segment() | "a" | std::env::consts::OS | segment());
+    assert_eq!(p, Path::new("segment").join("a").join(std::env::consts::OS).join("segment"));
+}
+
+#[test]
+fn expr_try() {
+    fn inner(fail: bool) -> Result<PathBuf, ()> {
+        Ok(path!("a" | fallible_segment(fail)? | "b"))
+    }
+    assert_eq!(inner(false), Ok(Path::new("a").join("fallible").join("b")));
+    assert_eq!(inner(true), Err(()));
+}
+
+#[test]
+fn expr_literal_method() {
+    let p = path!("a" | "b".to_uppercase() | "c");
+    assert_eq!(p, Path::new("a").join("B").join("c"));
+}
+
+#[test]
+fn try_path_ok() {
+    let home: Option<OsString> = Some(OsString::from("home"));
+    let name: Result<String, std::env::VarError> = Ok(String::from("name"));
+    let p = try_path!(home | ".config" | name | "a" | "b").unwrap();
+    assert_eq!(p, Path::new("home").join(".config").join("name").join("a").join("b"));
+}
+
+#[test]
+fn try_path_literals() {
+    let p = try_path!("a" | "b").unwrap();
+    assert_eq!(p, Path::new("a").join("b"));
+}
+
+#[test]
+fn try_path_none() {
+    let missing: Option<&str> = None;
+    let err = try_path!("a" | "b" | Some("c") | missing | "d").unwrap_err();
+    assert_eq!(err.segment(), 3);
+    assert!(err.is_none());
+    assert!(err.into_source().is_none());
+}
+
+#[test]
+fn try_path_err() {
+    let err = try_path!(Some("a") | "b" | std::env::var("PATH_DSL_DOES_NOT_EXIST") | "d").unwrap_err();
+    assert_eq!(err.segment(), 2);
+    assert!(!err.is_none());
+    assert_eq!(
+        err.to_string(),
+        format!("path segment 2 failed: {}", std::env::VarError::NotPresent)
+    );
+}
+
+#[test]
+fn try_path_infallible_segments() {
+    let root = PathBuf::from("root");
+    let name = "name";
+    let p = try_path!(&root | Some("a") | name | String::from("b") | 7).unwrap();
+    assert_eq!(p, Path::new("root").join("a").join("name").join("b").join("7"));
+
+    let err = try_path!(&root | name | None::<&str> | "c").unwrap_err();
+    assert_eq!(err.segment(), 2);
+}
+
+#[test]
+fn try_path_short_circuits() {
+    let mut evaluated = false;
+    let err = try_path!(None::<&str> | {
+        evaluated = true;
+        Some("a")
+    })
+    .unwrap_err();
+    assert_eq!(err.segment(), 0);
+    assert!(!evaluated);
+}
+
+#[test]
+fn try_path_steals_first_buffer() {
+    let mut first = PathBuf::with_capacity(100);
+    first.push("first");
+    let ptr = first.as_os_str() as *const OsStr as *const u8;
+    let p = try_path!(Some(first) | "a").unwrap();
+    assert_eq!(p, Path::new("first").join("a"));
+    assert_eq!(p.as_os_str() as *const OsStr as *const u8, ptr);
+}
+
+#[test]
+fn box_osstr_first_steals_buffer() {
+    let first: Box<OsStr> = OsString::from("first").into_boxed_os_str();
+    let ptr = &*first as *const OsStr as *const u8;
+    let p = path!(first | "a");
+    assert_eq!(p, Path::new("first").join("a"));
+    assert_eq!(p.as_os_str() as *const OsStr as *const u8, ptr);
+}
+
+#[test]
+fn optional_segment() {
+    let some: Option<&str> = Some("some");
+    let none: Option<String> = None;
+    let p = path!("a" | ?some | "b" | ?none | "c");
+    assert_eq!(p, Path::new("a").join("some").join("b").join("c"));
+
+    let p = path!(?None::<&str>);
+    assert_eq!(p, PathBuf::new());
+}
+
+#[test]
+fn optional_segment_first_steals_buffer() {
+    let mut first = PathBuf::with_capacity(100);
+    first.push("first");
+    let ptr = first.as_os_str() as *const OsStr as *const u8;
+    let p = path!(?Some(first) | "a");
+    assert_eq!(p, Path::new("first").join("a"));
+    assert_eq!(p.as_os_str() as *const OsStr as *const u8, ptr);
+}
+
+#[test]
+fn optional_segment_try_path() {
+    let missing: Option<&str> = None;
+    let err = try_path!("a" | ?Some("b") | missing).unwrap_err();
+    assert_eq!(err.segment(), 2);
+}
+
+#[test]
+fn div_option() {
+    let real = Path::new("a").join("b");
+    let some = Some(PathBuf::from("b"));
+    let none: Option<&str> = None;
+
+    assert_eq!(PathDSL::from("a") / some.clone() / none, real);
+    assert_eq!(&PathDSL::from("a") / some.as_ref() / none, real);
+    assert_eq!(&mut PathDSL::from("a") / some.as_deref() / none, real);
+    assert_eq!(&PathDSL::from("a") / none, *Path::new("a"));
+    assert_eq!(CopylessDSL::new() / None::<&str>, PathBuf::new());
+    assert_eq!(CopylessDSL::new() / Some("a") / some, real);
+}
+
+#[test]
+fn numeric_segments() {
+    let id: u64 = 42;
+    let offset: i8 = -3;
+    let real = Path::new("users").join("42").join("-3");
+
+    assert_eq!(PathDSL::from("users") / id / offset, real);
+    assert_eq!(&PathDSL::from("users") / 42 / -3, real);
+    assert_eq!(path!("users" | id | offset), real);
+    assert_eq!(path!("users" | 42 | -3), real);
+    assert_eq!(path!(id), PathBuf::from("42"));
+    assert_eq!(path!("users" | Some(id) | None::<u8> | offset), real);
+    assert_eq!(try_path!("users" | Some(id) | Some(offset)).unwrap(), real);
+    assert_eq!(path!(0usize | u128::MAX), Path::new("0").join(u128::MAX.to_string()));
+}
+
+struct UserId(u32);
+
+impl fmt::Display for UserId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "user-{}", self.0)
+    }
+}
+
+struct Rooted(&'static str);
+
+impl fmt::Display for Rooted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", MAIN_SEPARATOR, self.0)
+    }
+}
+
+display_segment!(UserId, Rooted);
+
+struct Shard(&'static str, u8);
+
+impl PathSegment for Shard {
+    fn push_onto(self, path: &mut PathDSL) {
+        path.push(self.0);
+        self.1.push_onto(path);
+    }
+}
+
+#[test]
+fn custom_segments() {
+    let real = Path::new("home").join("user-7").join("settings.toml");
+    assert_eq!(PathDSL::from("home") / UserId(7) / "settings.toml", real);
+    assert_eq!(path!("home" | UserId(7) | "settings.toml"), real);
+    assert_eq!(path!(UserId(7)), PathBuf::from("user-7"));
+    assert_eq!(path!("home" | Rooted("etc") | UserId(7)), path!(/ "etc" | "user-7"));
+
+    let real = Path::new("data").join("eu").join("3");
+    assert_eq!(PathDSL::from("data") / Shard("eu", 3), real);
+    assert_eq!(path!("data" | Shard("eu", 3)), real);
+}
+
+#[test]
+fn suffix_and_extension_operators() {
+    let real = Path::new("dir").join("file.tar.gz");
+    let suffix = String::from(".gz");
+    let ext = OsStr::new("tar");
+
+    let base = PathDSL::from("dir") / "file.txt";
+    assert_eq!(base.clone() % ext + ".gz", real);
+    assert_eq!(&base % "tar" + suffix.clone(), real);
+    assert_eq!(&mut base.clone() % String::from("tar") + OsStr::new(".gz"), real);
+    assert_eq!(base, Path::new("dir").join("file.txt"));
+
+    let base = PathDSL::from("dir") / "file";
+    assert_eq!(base.clone() + ".tar" + &suffix, real);
+    assert_eq!(&base + ".tar.gz", real);
+    assert_eq!(&mut base.clone() + OsString::from(".tar") + suffix, real);
+    assert_eq!((PathDSL::from("dir") / "file" + ".tar") / "inner", Path::new("dir").join("file.tar").join("inner"));
+    assert_eq!(PathDSL::from("dir") % "txt", PathBuf::from("dir.txt"));
+}
+
+#[test]
+#[should_panic(expected = "suffix or extension contains a separator or nul")]
+fn suffix_with_separator() {
+    let _ = PathDSL::from("dir") + "a/b";
+}
+
+#[test]
+#[should_panic(expected = "suffix or extension contains a separator or nul")]
+fn extension_with_separator() {
+    let _ = PathDSL::from("dir") % String::from("x/y");
+}
+
+macro_rules! splat_test {
+    (name: $name:ident, $parts:expr) => {
+        #[test]
+        fn $name() {
+            let mut real = PathBuf::new();
+            real.push("a");
+            real.push("b");
+            real.push("c");
+            real.push("d");
+            real.push("e");
+
+            let parts = $parts;
+            assert_eq!(path!("a" | "b" | ..parts | "e"), real);
+            let parts = $parts;
+            assert_eq!(path!("a" | ..parts | "d" | "e"), Path::new("a").join("c").join("d").join("d").join("e"));
+            let parts = $parts;
+            assert_eq!(path!(..parts), Path::new("c").join("d"));
+        }
+    };
+}
+
+splat_test!(name: splat_array, ["c", "d"]);
+splat_test!(name: splat_slice, &[String::from("c"), String::from("d")][..]);
+splat_test!(name: splat_vec, vec![PathBuf::from("c"), PathBuf::from("d")]);
+splat_test!(name: splat_components, Path::new("c/d").components());
+splat_test!(name: splat_iter, Path::new("c/d").iter());
+
+#[test]
+fn splat_keeps_literal_runs() {
+    let parts = ["c", "d"];
+    let p = path!(..parts | "e" | "f");
+    assert_eq!(p, Path::new("c").join("d").join("e").join("f"));
+    let p = try_path!("a" | ..parts | Some("e")).unwrap();
+    assert_eq!(p, Path::new("a").join("c").join("d").join("e"));
+    let err = try_path!("a" | ..parts | None::<&str>).unwrap_err();
+    assert_eq!(err.segment(), 2);
+}
+
+#[test]
+fn format_segment() {
+    let n = 42;
+    let id = "abc";
+    let p = path!("out" | "frames" | {"frame_{:04}.png", n});
+    assert_eq!(p, Path::new("out").join("frames").join("frame_0042.png"));
+    let p = path!({"run-{}", id} | "a" | {"{id}.log"});
+    assert_eq!(p, Path::new("run-abc").join("a").join("abc.log"));
+    let p = path!({"{x}-{y}", x = 1, y = n,});
+    assert_eq!(p, Path::new("1-42"));
+}
+
+#[test]
+fn format_segment_separators() {
+    // A trailing separator on the previous segment isn't doubled up.
+    let p = PathDSL::from("a/") / format_args!("b");
+    assert_eq!(p.as_os_str(), Path::new("a/").join("b").as_os_str());
+    let p = &PathDSL::new() / format_args!("b");
+    assert_eq!(p.as_os_str(), OsStr::new("b"));
+    let p = &mut PathDSL::from("a") / format_args!("{}", 'b') / "c";
+    assert_eq!(p, Path::new("a").join("b").join("c"));
+
+    // A leading root replaces the path, the same as pushing the formatted string.
+    let root = format!("{}b", MAIN_SEPARATOR);
+    let p = path!("a" | {"{}b", MAIN_SEPARATOR} | "c");
+    assert_eq!(p, path!("a" | &root | "c"));
+    assert_eq!(p, Path::new(&root).join("c"));
+    let p = PathDSL::from("a") / format_args!("{}{}", "", root);
+    assert_eq!(p, PathBuf::from(&root));
+}
+
+#[test]
+fn format_segment_try_path() {
+    let err = try_path!("a" | {"{}", 1} | None::<&str>).unwrap_err();
+    assert_eq!(err.segment(), 2);
+}
+
+#[test]
+fn conditional_if_else() {
+    for &(release, expected) in &[(true, "release"), (false, "debug")] {
+        let bin = String::from("bin");
+        let p = path!("target" | if release { "release" } else { "debug" } | bin);
+        assert_eq!(p, Path::new("target").join(expected).join("bin"));
+    }
+}
+
+#[test]
+fn conditional_else_if_chain() {
+    for &(arch, expected) in &[(0, "x86"), (1, "arm"), (2, "other")] {
+        let p = path!("a" | if arch == 0 { "x86" } else if arch == 1 { "arm" } else { "other" | "c" } | "b");
+        if arch == 2 {
+            assert_eq!(p, Path::new("a").join(expected).join("c").join("b"));
+        } else {
+            assert_eq!(p, Path::new("a").join(expected).join("b"));
+        }
+    }
+}
+
+#[test]
+fn conditional_without_else() {
+    for &verbose in &[true, false] {
+        let p = path!("root" | if verbose { "logs" | "verbose" } | "out.txt");
+        let q = path!("root" | if verbose => "logs" | "out.txt");
+        if verbose {
+            assert_eq!(p, Path::new("root").join("logs").join("verbose").join("out.txt"));
+            assert_eq!(q, Path::new("root").join("logs").join("out.txt"));
+        } else {
+            assert_eq!(p, Path::new("root").join("out.txt"));
+            assert_eq!(q, Path::new("root").join("out.txt"));
+        }
+    }
+}
+
+#[test]
+fn conditional_mixed_types() {
+    let owned = PathBuf::from("owned");
+    let p = path!(if owned.as_os_str().len() > 100 { "short" } else { &owned | "x" } | "y");
+    assert_eq!(p, Path::new("owned").join("x").join("y"));
+    let p = path!(if let Some(name) = Some("name") => name);
+    assert_eq!(p, Path::new("name"));
+}
+
+#[test]
+fn conditional_first_steals_buffer() {
+    let mut first = PathBuf::with_capacity(100);
+    first.push("first");
+    let ptr = first.as_os_str() as *const OsStr as *const u8;
+    let p = path!(if true => first | "a");
+    assert_eq!(p, Path::new("first").join("a"));
+    assert_eq!(p.as_os_str() as *const OsStr as *const u8, ptr);
+}
+
+#[test]
+fn conditional_try_path() {
+    let missing: Option<&str> = None;
+    let err = try_path!("a" | if false => Some("b") | missing).unwrap_err();
+    assert_eq!(err.segment(), 2);
+    let err = try_path!(if true { "a" | Some("b") } else { missing } | missing).unwrap_err();
+    assert_eq!(err.segment(), 2);
+}
+
+#[test]
+fn cfg_segments() {
+    let p = path!("a" | #[cfg(all())] "b" | #[cfg(any())] "c" | "d");
+    assert_eq!(p, Path::new("a").join("b").join("d"));
+
+    let x = String::from("x");
+    let p = path!(#[cfg(any())] "a" | x | #[cfg(all())] { "b" } | #[cfg(any())] "c");
+    assert_eq!(p, Path::new("x").join("b"));
+
+    let p = path!(#[cfg(any())] "a");
+    assert_eq!(p, PathBuf::new());
+}
+
+#[test]
+fn cfg_segments_concatenate_literals() {
+    let p = path!("a" | #[cfg(any())] "b" | "c" | #[cfg(all())] "d" | "e");
+    assert_eq!(p, Path::new("a").join("c").join("d").join("e"));
+    let expected = Into::<PathBuf>::into(CopylessDSL::new() / concat_separator!("a", "c", "d", "e"));
+    assert_eq!(p, expected);
+    assert_eq!(p.capacity(), p.as_os_str().len());
+}
+
+#[test]
+fn cfg_segments_nested() {
+    let p = path!(#[cfg(all())] #[cfg(any())] "a" | #[cfg(all())] #[cfg(all())] "b");
+    assert_eq!(p, Path::new("b"));
+}
+
+#[test]
+fn cfg_segments_try_path() {
+    let err = try_path!(#[cfg(any())] "a" | "b" | #[cfg(any())] Some("c") | None::<&str>).unwrap_err();
+    assert_eq!(err.segment(), 1);
+}
+
+#[test]
+fn extension_literal_folding() {
+    let p = path!("dir" | "archive" . "tar" . "gz" | "file" + ".bak");
+    let expected = Into::<PathBuf>::into(CopylessDSL::new() / concat_separator!("dir", "archive.tar.gz", "file.bak"));
+    assert_eq!(p, expected);
+    assert_eq!(p, Path::new("dir").join("archive.tar.gz").join("file.bak"));
+    assert_eq!(p.capacity(), p.as_os_str().len());
+}
+
+#[test]
+fn extension_runtime() {
+    let stem = String::from("stem");
+    let name = Path::new("name.txt");
+    let f = Fields::new();
+
+    assert_eq!(path!("dir" | stem . "tar.gz"), Path::new("dir").join("stem.tar.gz"));
+    assert_eq!(path!(&f.root + "_old" | "a"), Path::new("root_old").join("a"));
+    assert_eq!(path!("dir" | name % "json"), Path::new("dir").join("name.json"));
+    assert_eq!(path!("dir" | name + ".bak" % "old"), Path::new("dir").join("name.txt.old"));
+    assert_eq!(path!("dir" | "name.txt" % "json" | "x"), Path::new("dir").join("name.json").join("x"));
+    assert_eq!(path!(f.root() . "d"), Path::new("root.d"));
+}
+
+#[test]
+fn extension_runtime_arguments() {
+    let stem = "stem";
+    let ext = String::from("json");
+    let suffix = OsString::from("_old");
+    let exts = ("tar", "gz");
+    let kind = "log";
+
+    assert_eq!(path!("dir" | "stem" . kind), Path::new("dir").join("stem.log"));
+    assert_eq!(path!("dir" | stem . (&ext) | "x"), Path::new("dir").join("stem.json").join("x"));
+    assert_eq!(path!("dir" | "stem" . (exts.0) . (exts.1)), Path::new("dir").join("stem.tar.gz"));
+    assert_eq!(path!(stem + &suffix % &ext), Path::new("stem_old.json"));
+    assert_eq!(path!("name.txt" % (ext.as_str()) | "x"), Path::new("name.json").join("x"));
+    assert_eq!(path!(stem + (ext.clone() + "s")), Path::new("stemjsons"));
+    assert_eq!(try_path!(Some(stem) . (ext) + suffix).unwrap(), Path::new("stem.json_old"));
+}
+
+#[test]
+#[should_panic(expected = "separator or nul")]
+fn extension_runtime_with_separator() {
+    let ext = format!("a{}b", MAIN_SEPARATOR);
+    let _ = path!("dir" | "stem" . ext);
+}
+
+#[test]
+fn extension_not_modifier() {
+    let pair = ("a", "b");
+    let a = String::from("a");
+    assert_eq!(path!((pair.0) | a.clone() + "b" + &a + "c"), Path::new("a").join("abac"));
+    assert_eq!(path!(pair.0.to_string() + ".x"), Path::new("a.x"));
+}
+
+#[test]
+fn extension_tuple_fields() {
+    struct Wrapper(&'static str, PathBuf);
+
+    let pair = ("a", "b");
+    let nested = (("x", "y"), "z");
+    let wide = (0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, "eleven");
+    let w = Wrapper("w", PathBuf::from("buf"));
+    assert_eq!(path!("r" | pair.0), Path::new("r").join("a"));
+    assert_eq!(path!(pair.1 | "r"), Path::new("b").join("r"));
+    assert_eq!(path!("r" | nested.0.1 | nested.1), Path::new("r").join("y").join("z"));
+    assert_eq!(path!(wide.11 | wide.3), Path::new("eleven").join("3"));
+    assert_eq!(path!("r" | &w.1 | w.0), Path::new("r").join("buf").join("w"));
+    assert_eq!(path!("r" | pair.0 . "txt" | pair.1 + "_old"), Path::new("r").join("a.txt").join("b_old"));
+    assert_eq!(path!(pair.0.to_owned() | wide.11.len()), Path::new("a").join("6"));
+    assert_eq!(try_path!("r" | Some(pair.0) | Some(pair).map(|p| p.1)).unwrap(), Path::new("r").join("a").join("b"));
+}
+
+#[test]
+fn extension_arithmetic() {
+    let n: u32 = 7;
+    let wide = (0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16u32);
+    assert_eq!(path!("a" | n + 1), Path::new("a").join("8"));
+    assert_eq!(path!("a" | n % 2 | n * 2 + 1), Path::new("a").join("1").join("15"));
+    assert_eq!(path!(n - 5 % 3 | n - 1 + 2), Path::new("5").join("8"));
+    assert_eq!(path!(n << 1 + 1 | n + n + 1), Path::new("28").join("15"));
+    assert_eq!(path!(n + 1 . "txt" | n + "px"), Path::new("8.txt").join("7px"));
+    assert_eq!(path!(n + 2 % 3 | n + 1 + 2 % 2), Path::new("9").join("8"));
+    assert_eq!(path!("a" | (wide.16) + 1 | wide.16.pow(2) + 1), Path::new("a").join("17").join("257"));
+}
+
+#[test]
+fn extension_try_path() {
+    let p = try_path!(Some("name") . "json" | "a" + "b").unwrap();
+    assert_eq!(p, Path::new("name.json").join("ab"));
+    let err = try_path!("a" % "b" | None::<&str>).unwrap_err();
+    assert_eq!(err.segment(), 1);
+}
+
+#[test]
+fn root_segments() {
+    let root = PathBuf::from(MAIN_SEPARATOR.to_string());
+    let name = "etc";
+    assert_eq!(path!(/), root);
+    assert_eq!(path!(/ "etc" | "app"), root.join("etc").join("app"));
+    assert_eq!(path!(/ | "etc"), root.join("etc"));
+    assert_eq!(path!(/ name | "app"), root.join("etc").join("app"));
+    assert_eq!(path!(/ "app" . "d"), root.join("app.d"));
+    assert_eq!(path!("a" | / "etc"), root.join("etc"));
+}
+
+#[test]
+fn relative_segments() {
+    let name = "a";
+    assert_eq!(path!(.. | .. | "lib"), Path::new("..").join("..").join("lib"));
+    assert_eq!(path!(name | . | ..), Path::new("a").join(".").join(".."));
+    assert_eq!(path!(..), Path::new(".."));
+    assert_eq!(path!(.), Path::new("."));
+    assert_eq!(try_path!(.. | Some("a")).unwrap(), Path::new("..").join("a"));
+}
+
+#[test]
+fn drive_segments() {
+    #[cfg(windows)]
+    let share = PathBuf::from("\\\\server\\share\\");
+    #[cfg(not(windows))]
+    let share = PathBuf::from("//server/share/");
+
+    #[cfg(windows)]
+    {
+        let name = "Users";
+        let drive = PathBuf::from("C:\\");
+        assert_eq!(path!(C:), drive);
+        assert_eq!(path!(C: | "Users" | "me"), drive.join("Users").join("me"));
+        assert_eq!(path!(C: | name), drive.join("Users"));
+        assert_eq!(path!(d: | "data"), PathBuf::from("d:\\data"));
+    }
+    assert_eq!(path!(#[cfg(windows)] C: | "data"), Path::new(if cfg!(windows) { "C:\\data" } else { "data" }));
+    assert_eq!(path!(unc "server" "share"), share);
+    assert_eq!(path!(unc "server" "share" | "dir"), share.join("dir"));
+}
+
+#[test]
+fn checked_literals() {
+    assert_eq!(path!("usr" | raw "local/bin" | "x"), Path::new("usr").join("local/bin").join("x"));
+    assert_eq!(path!(raw " a " | "b"), Path::new(" a ").join("b"));
+    assert_eq!(path!("console" | "com0" | "lpt" | "auxiliary"), Path::new("console/com0/lpt/auxiliary"));
+    assert_eq!(path!(".config" | "a.b.c" | "" | "x"), Path::new(".config/a.b.c/").join("x"));
+    assert_eq!(path!("a" . "tar" . "gz"), Path::new("a.tar.gz"));
+}
+
+#[test]
+fn output_types() {
+    let name = "b";
+    let arc: Arc<Path> = path!("a" | name => Arc<Path>);
+    assert_eq!(&*arc, Path::new("a").join("b"));
+    let rc: Rc<Path> = path!("a" | "b" => Rc<Path>);
+    assert_eq!(&*rc, Path::new("a").join("b"));
+    let boxed: Box<Path> = path!(name | "c" => Box<Path>);
+    assert_eq!(&*boxed, Path::new("b").join("c"));
+    let os: OsString = path!("a" => OsString);
+    assert_eq!(os, OsString::from("a"));
+    let cow: Cow<'static, Path> = path!("a" | name => Cow<'static, Path>);
+    assert_eq!(cow, Path::new("a").join("b"));
+    let dsl: PathDSL = path!("a" | "b" => PathDSL);
+    assert_eq!(dsl, *Path::new("a").join("b"));
+    let empty: Box<Path> = path!(=> Box<Path>);
+    assert_eq!(&*empty, Path::new(""));
+}
+
+#[test]
+fn output_type_segments() {
+    let name = "n";
+    let f = Fields::new();
+    let parts = ["x", "y"];
+    let real = Path::new("a").join("n");
+    assert_eq!(*path!("a" | name => Arc<Path>), *real);
+    assert_eq!(*path!("a" | &name => Arc<Path>), *real);
+    assert_eq!(*path!("a" | f.root() => Arc<Path>), *Path::new("a").join("root"));
+    assert_eq!(*path!("a" | raw "b/c" => Arc<Path>), *Path::new("a").join("b/c"));
+    assert_eq!(*path!("a" | .. => Arc<Path>), *Path::new("a").join(".."));
+    assert_eq!(*path!("a" | . => Arc<Path>), *Path::new("a").join("."));
+    assert_eq!(*path!("a" | { "{}", name } => Arc<Path>), *real);
+    assert_eq!(*path!("a" | ..parts => Arc<Path>), *Path::new("a").join("x").join("y"));
+    assert_eq!(*path!("a" | ?Some(name) => Arc<Path>), *real);
+    assert_eq!(*path!("a" | "b" . "txt" => Arc<Path>), *Path::new("a").join("b.txt"));
+    assert_eq!(*path!("a" | name % "txt" => Arc<Path>), *Path::new("a").join("n.txt"));
+    assert_eq!(*path!("a" | if true => name => Arc<Path>), *real);
+    assert_eq!(*path!("a" | if false { name } => Arc<Path>), *Path::new("a"));
+    assert_eq!(*path!("a" | if false { "b" } else { name } => Arc<Path>), *real);
+    assert_eq!(*path!("a" | #[cfg(all())] name => Arc<Path>), *real);
+    assert_eq!(*path!("a" | #[cfg(any())] name => Arc<Path>), *Path::new("a"));
+    assert_eq!(*path!(/ => Arc<Path>), *PathBuf::from(MAIN_SEPARATOR.to_string()));
+}
+
+#[test]
+fn output_type_try_path() {
+    let arc: Arc<Path> = try_path!("a" | Some("b") => Arc<Path>).unwrap();
+    assert_eq!(&*arc, Path::new("a").join("b"));
+    let err = try_path!("a" | None::<&str> => Arc<Path>).unwrap_err();
+    assert_eq!(err.segment(), 1);
+}
+
+const STATIC_PATH: &str = static_path_str!("etc" | "app" | "config.toml");
+
+#[test]
+fn static_paths() {
+    let real = Path::new("etc").join("app").join("config.toml");
+    assert_eq!(Path::new(STATIC_PATH), real);
+    assert_eq!(static_path!("etc" | "app" | "config.toml"), real);
+    assert_eq!(static_path!(.. | . | "a"), Path::new("..").join(".").join("a"));
+    assert_eq!(static_path!(/ "etc"), PathBuf::from(MAIN_SEPARATOR.to_string()).join("etc"));
+    assert_eq!(static_path!(), Path::new(""));
+    assert_eq!(
+        static_path!(env!("CARGO_PKG_NAME") | "src"),
+        Path::new(env!("CARGO_PKG_NAME")).join("src")
+    );
+    assert_eq!(static_path_str!("a"), "a");
+}
+
+#[test]
+fn static_path_include() {
+    let lib = include_str!(static_path_str!(env!("CARGO_MANIFEST_DIR") | "src" | "tests.rs"));
+    assert!(lib.contains("fn static_path_include()"));
+}
+
+#[test]
+fn path_push_buffers() {
+    let name = "n";
+    let mut buf = PathBuf::from("a");
+    path_push!(buf | "b" | name);
+    assert_eq!(buf, Path::new("a").join("b").join("n"));
+
+    let by_ref = &mut buf;
+    path_push!(by_ref | "c" . "txt");
+    assert_eq!(buf, Path::new("a").join("b").join("n").join("c.txt"));
+
+    let mut dsl = PathDSL::from("a");
+    path_push!(dsl | if true => name | ..["x", "y"]);
+    assert_eq!(dsl, *Path::new("a").join("n").join("x").join("y"));
+    path_push!(&mut dsl | "z");
+    assert_eq!(dsl, *Path::new("a").join("n").join("x").join("y").join("z"));
+    path_push!(dsl);
+    assert_eq!(dsl, *Path::new("a").join("n").join("x").join("y").join("z"));
+
+    let mut f = Fields::new();
+    path_push!(f.root | { "{}-{}", 1, 2 });
+    assert_eq!(f.root, Path::new("root").join("1-2"));
+}
+
+#[test]
+fn path_push_keeps_buffer() {
+    let mut buf = PathBuf::with_capacity(64);
+    buf.push("a");
+    let ptr = buf.as_os_str().as_encoded_bytes().as_ptr();
+    path_push!(buf | "b" | "c");
+    assert_eq!(buf.as_os_str().as_encoded_bytes().as_ptr(), ptr);
+    assert_eq!(buf.capacity(), 64);
+}
+
+#[test]
+fn path_into_buffers() {
+    let root = PathBuf::from("root");
+    let mut buf = PathBuf::with_capacity(64);
+    for name in &["a", "b", "c"] {
+        path_into!(buf; &root | "dir" | name);
+        assert_eq!(buf, root.join("dir").join(name));
+        assert_eq!(buf.capacity(), 64);
+    }
+
+    let mut dsl = PathDSL::from("old");
+    path_into!(&mut dsl; "new" | "a");
+    assert_eq!(dsl, *Path::new("new").join("a"));
+    path_into!(dsl;);
+    assert_eq!(dsl, *Path::new(""));
+}
+
+#[test]
+fn path_into_owned_first_keeps_buffer() {
+    let mut buf = PathBuf::with_capacity(64);
+    let ptr = buf.as_os_str().as_encoded_bytes().as_ptr();
+    path_into!(buf; String::from("owned") | "x");
+    assert_eq!(buf, Path::new("owned").join("x"));
+    assert_eq!(buf.capacity(), 64);
+    assert_eq!(buf.as_os_str().as_encoded_bytes().as_ptr(), ptr);
+
+    path_into!(buf; PathBuf::from("buf") | "y");
+    assert_eq!(buf, Path::new("buf").join("y"));
+    assert_eq!(buf.capacity(), 64);
+
+    let mut dsl = PathDSL::from(PathBuf::with_capacity(64));
+    path_into!(dsl; OsString::from("os") | PathDSL::from("dsl"));
+    assert_eq!(dsl, *Path::new("os").join("dsl"));
+    assert_eq!(dsl.capacity(), 64);
+}
+
+#[test]
+fn in_place_early_return_keeps_buffer() {
+    fn push(buf: &mut PathBuf, seg: Option<&str>) -> Option<()> {
+        path_push!(buf | "dir" | seg? | ..["x"]);
+        Some(())
+    }
+    fn into(buf: &mut PathDSL, seg: Option<&str>) -> Option<()> {
+        path_into!(buf; "x" | if true => seg?);
+        Some(())
+    }
+
+    let mut buf = PathBuf::from("/home/user/project");
+    assert_eq!(push(&mut buf, None), None);
+    assert_eq!(buf, Path::new("/home/user/project"));
+    assert_eq!(push(&mut buf, Some("a")), Some(()));
+    assert_eq!(buf, Path::new("/home/user/project/dir/a/x"));
+
+    let mut dsl = PathDSL::from("/home/user/project");
+    assert_eq!(into(&mut dsl, None), None);
+    assert_eq!(dsl, *Path::new("/home/user/project"));
+    assert_eq!(into(&mut dsl, Some("a")), Some(()));
+    assert_eq!(dsl, *Path::new("x/a"));
+}
+
+#[test]
+fn in_place_panic_keeps_buffer() {
+    fn boom() -> &'static str {
+        panic!("segment")
+    }
+
+    let mut buf = PathBuf::from("root");
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        path_push!(buf | "dir" | boom());
+    }));
+    assert!(result.is_err());
+    assert_eq!(buf, Path::new("root"));
+
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        path_into!(buf; "dir" | boom());
+    }));
+    assert!(result.is_err());
+    assert_eq!(buf, Path::new("root"));
+}
+
+#[test]
+fn presized_capacity() {
+    let name = "name";
+    let opt = Some(String::from("optional"));
+    let p = path!(String::from("owned") | name | "lit" | PathBuf::from("buf") | opt | "end");
+    assert_eq!(p, Path::new("owned/name/lit/buf/optional/end"));
+    assert_eq!(p.capacity(), p.as_os_str().len());
+
+    let p = path!(name | &name | Path::new(name));
+    assert_eq!(p.capacity(), p.as_os_str().len());
+}
+
+#[test]
+fn presized_temporaries() {
+    let p = path!(String::from("a").as_str() | Path::new(&String::from("b")) | "c");
+    assert_eq!(p, Path::new("a").join("b").join("c"));
+}
+
+#[test]
+fn presized_evaluation_order() {
+    let order = std::cell::RefCell::new(Vec::new());
+    let seg = |n: u32| {
+        order.borrow_mut().push(n);
+        n.to_string()
+    };
+    let p = path!(seg(1) | if { seg(2); true } => seg(3) | seg(4) | ..vec![seg(5)] | seg(6));
+    assert_eq!(p, Path::new("1/3/4/5/6"));
+    assert_eq!(*order.borrow(), [1, 2, 3, 4, 5, 6]);
+}
+
+fn test_env(name: &str) -> Option<OsString> {
+    match name {
+        "HOME" => Some(OsString::from("/home/me")),
+        "EMPTY" => Some(OsString::new()),
+        "APP" => Some(OsString::from("app")),
+        _ => None,
+    }
+}
+
+#[test]
+fn expand_env_templates() {
+    let expand = |template| expand_env_in(template, &test_env);
+    assert_eq!(expand("$HOME/.cache").unwrap(), "/home/me/.cache");
+    assert_eq!(expand("${HOME}x").unwrap(), "/home/mex");
+    assert_eq!(expand("$APP-$APP").unwrap(), "app-app");
+    assert_eq!(expand("${CACHE:-$HOME/.cache}/$APP").unwrap(), "/home/me/.cache/app");
+    assert_eq!(expand("${EMPTY:-default}").unwrap(), "default");
+    assert_eq!(expand("${EMPTY}x").unwrap(), "x");
+    assert_eq!(expand("${A:-${B:-nested}}").unwrap(), "nested");
+    assert_eq!(expand("$$HOME $ $1 ${HOME").unwrap(), "$HOME $ $1 ${HOME");
+    assert_eq!(expand("plain").unwrap(), "plain");
+}
+
+#[test]
+fn expand_env_missing() {
+    let expand = |template| expand_env_in(template, &test_env);
+    assert_eq!(expand("$HOME/$MISSING").unwrap_err().name(), "MISSING");
+    assert_eq!(expand("${MISSING}").unwrap_err().name(), "MISSING");
+    assert_eq!(expand("${A:-$MISSING}").unwrap_err().name(), "MISSING");
+    assert_eq!(expand("$MISSING").unwrap_err().to_string(), "environment variable `MISSING` is not set");
+}
+
+#[test]
+fn env_path_segments() {
+    // Set variables are covered by `expand_env_in`, which `env_path!` calls with the process environment.
+    let name = "name";
+    let root = expand_env_in("${APP}_root", &test_env).unwrap();
+    let p = env_path!(&root | "${PATH_DSL_TEST_UNSET:-a/b}" | name | "c" . "txt").unwrap();
+    assert_eq!(p, Path::new("app_root").join("a/b").join("name").join("c.txt"));
+
+    let err = env_path!("a" | name | "${PATH_DSL_TEST_UNSET:-x}" | "$PATH_DSL_TEST_UNSET").unwrap_err();
+    assert_eq!(err.segment(), 3);
+    let source = err.into_source().unwrap();
+    assert_eq!(source.downcast_ref::<MissingEnvVar>().unwrap().name(), "PATH_DSL_TEST_UNSET");
+}
+
+#[test]
+fn env_path_compile_time() {
+    let p = static_path_str!(env!("CARGO_MANIFEST_DIR") | "src");
+    assert_eq!(Path::new(p), Path::new(env!("CARGO_MANIFEST_DIR")).join("src"));
+}
+
+#[test]
+fn expand_home_paths() {
+    let env = |name: &str| match name {
+        "HOME" | "USERPROFILE" => Some(OsString::from("/home/me")),
+        "USER" | "USERNAME" => Some(OsString::from("me")),
+        _ => None,
+    };
+    let expand = |path: &str| PathDSL::from(path).expand_home_in(&env);
+    assert_eq!(expand("~").unwrap(), PathBuf::from("/home/me"));
+    assert_eq!(expand("~/.config/app").unwrap(), PathBuf::from("/home/me/.config/app"));
+    assert_eq!(expand("~me/.config").unwrap(), PathBuf::from("/home/me/.config"));
+    assert_eq!(expand("dir/~").unwrap(), PathBuf::from("dir/~"));
+    assert_eq!(expand("/~").unwrap(), PathBuf::from("/~"));
+
+    let err = expand("~you/.config").unwrap_err();
+    assert_eq!(err.user(), Some("you"));
+    assert_eq!(err.to_string(), "home directory of user `you` is unknown");
+}
+
+#[test]
+fn expand_home_missing() {
+    let env = |name: &str| match name {
+        "HOME" | "USERPROFILE" => Some(OsString::new()),
+        _ => None,
+    };
+    let err = PathDSL::from("~/dir").expand_home_in(&env).unwrap_err();
+    assert_eq!(err.user(), None);
+    assert_eq!(err.to_string(), "home directory of the current user is unknown");
+    assert_eq!(PathDSL::from("dir").expand_home_in(&env).unwrap(), PathBuf::from("dir"));
+}
+
+#[test]
+fn expand_home_other_users() {
+    struct Users;
+    impl EnvContext for Users {
+        fn var_os(&self, _name: &str) -> Option<OsString> {
+            None
+        }
+        fn user_home(&self, user: &str) -> Option<PathBuf> {
+            Some(PathBuf::from("/users").join(user))
+        }
+    }
+    let expanded = PathDSL::from("~you/dir").expand_home_in(&Users).unwrap();
+    assert_eq!(expanded, PathBuf::from("/users/you/dir"));
+    assert!(PathDSL::from("~/dir").expand_home_in(&Users).is_err());
+}
+
+#[test]
+fn home_segments() {
+    let home = ProcessEnv.home_dir(None);
+    match &home {
+        Ok(home) => {
+            assert_eq!(try_path!(~).unwrap(), *home);
+            assert_eq!(try_path!(~ | ".config" | "app").unwrap(), home.join(".config").join("app"));
+            assert_eq!(try_path!(~ => OsString).unwrap(), home.as_os_str());
+            let name = "app";
+            assert_eq!(try_path!(~ | name).unwrap(), home.join("app"));
+        }
+        Err(_) => assert!(try_path!(~ | "dir").is_err()),
+    }
+    let built = try_path!(~ | "dir").map_err(|e| e.segment());
+    assert_eq!(built, home.map(|h| h.join("dir")).map_err(|_| 0));
+    assert_eq!(try_path!(~no_such_user_path_dsl | "dir").unwrap_err().segment(), 0);
+}
+
+#[cfg(feature = "proc-macro")]
+#[test]
+fn slash_path() {
+    use crate::slash::path as slash;
+
+    let root = PathBuf::from("root");
+    let file = String::from("file.txt");
+    let names = ["a", "b"];
+    assert_eq!(slash!(), PathBuf::new());
+    assert_eq!(slash!("dir"), PathBuf::from("dir"));
+    assert_eq!(
+        slash!(root.clone() / "dir" / names.join("_") / &file),
+        root.join("dir").join("a_b").join("file.txt")
+    );
+    assert_eq!(slash!(&root / "a" / "b" / "c"), path!(&root | "a" | "b" | "c"));
+    assert_eq!(slash!(.. / . / "lib"), PathBuf::from("..").join(".").join("lib"));
+    assert_eq!(slash!("dir" / raw "a/b"), PathBuf::from("dir").join("a/b"));
+    assert_eq!(slash!("dir" / (7 / 2).to_string()), PathBuf::from("dir").join("3"));
+    assert_eq!(slash!("a" / "b" => OsString), PathBuf::from("a").join("b").into_os_string());
+    assert_eq!(slash!(&root / "dir" => Box<Path>), root.join("dir").into_boxed_path());
+}
+
+#[cfg(feature = "proc-macro")]
+#[test]
+fn slash_path_roots() {
+    use crate::slash::path as slash;
+
+    let root = MAIN_SEPARATOR.to_string();
+    assert_eq!(slash!(/), PathBuf::from(&root));
+    assert_eq!(slash!(/ "etc" / "app"), PathBuf::from(&root).join("etc").join("app"));
+    let name = "app";
+    assert_eq!(slash!(/ name / "config"), PathBuf::from(&root).join("app").join("config"));
+}
+
+#[cfg(feature = "proc-macro")]
+#[test]
+fn slash_path_folds_literals() {
+    use crate::slash::path as slash;
+
+    let folded = slash!("a" / "b" / "c" / "d" / "e" / "f" / "g" / "h" / "i" / "j" / "k" / "l" / "m" / "n" / "o" / "p" / "q" / "r");
+    let expected = path!("a" | "b" | "c" | "d" | "e" | "f" | "g" | "h" | "i" | "j" | "k" | "l" | "m" | "n" | "o" | "p" | "q" | "r");
+    assert_eq!(folded, expected);
+    assert_eq!(folded.components().count(), 18);
+}
+
+#[test]
+fn paths_alternatives() {
+    let sources: Vec<PathBuf> = paths!("src" | {"lib", "main"} . "rs").collect();
+    assert_eq!(sources, vec![Path::new("src").join("lib.rs"), Path::new("src").join("main.rs")]);
+
+    let root = PathBuf::from("root");
+    let name = String::from("name");
+    let all: Vec<PathBuf> = paths!(&root | {"a", name.as_str(),} | "dir" | "sub" | {"x", "y"}).collect();
+    let expected: Vec<PathBuf> = ["a", "name"]
+        .iter()
+        .flat_map(|first| ["x", "y"].iter().map(move |second| (first, second)))
+        .map(|(first, second)| root.join(first).join("dir").join("sub").join(second))
+        .collect();
+    assert_eq!(all, expected);
+
+    assert_eq!(paths!("only" | "fixed").collect::<Vec<_>>(), vec![Path::new("only").join("fixed")]);
+    assert_eq!(paths!().collect::<Vec<_>>(), vec![PathBuf::new()]);
+}
+
+#[test]
+fn paths_ranges_and_iterators() {
+    let shards: Vec<PathBuf> = paths!("shard_" + {0..3} | "data.bin").collect();
+    let expected: Vec<PathBuf> = (0..3).map(|i| Path::new(&format!("shard_{}", i)).join("data.bin")).collect();
+    assert_eq!(shards, expected);
+
+    let formats = vec!["png", "jpg"];
+    let images: Vec<PathBuf> = paths!("assets" | {1..=2, 4} + "x" | {..&formats}).collect();
+    assert_eq!(images.len(), 6);
+    assert_eq!(images[0], Path::new("assets").join("1x").join("png"));
+    assert_eq!(images[3], Path::new("assets").join("2x").join("jpg"));
+    assert_eq!(images[5], Path::new("assets").join("4x").join("jpg"));
+
+    let n = 2;
+    assert_eq!(paths!({0..n} % "txt").collect::<Vec<_>>(), vec![PathBuf::from("0.txt"), PathBuf::from("1.txt")]);
+}
+
+#[test]
+fn paths_range_expressions() {
+    let n = 1;
+    let names = ["a", "b", "c"];
+    let numbers = |paths: crate::Paths| paths.map(|p| p.to_str().unwrap().to_owned()).collect::<Vec<_>>();
+    assert_eq!(numbers(paths!({0..n + 1})), ["0", "1"]);
+    assert_eq!(numbers(paths!({-1..=n})), ["-1", "0", "1"]);
+    assert_eq!(numbers(paths!({names.len() - 1..names.len() + 1})), ["2", "3"]);
+    assert_eq!(numbers(paths!({n..=n * 2, names.len()..5})), ["1", "2", "3", "4"]);
+    assert_eq!(numbers(paths!({'x'..='z'})), ["x", "y", "z"]);
+    assert_eq!(paths!("a" | {.., "b"}).collect::<Vec<_>>(), vec![Path::new("a").join(".."), Path::new("a").join("b")]);
+}
+
+#[test]
+fn paths_iterator() {
+    let empty: Vec<&str> = Vec::new();
+    let mut none = paths!("a" | {..empty} | {"b", "c"});
+    assert_eq!(none.len(), 0);
+    assert_eq!(none.next(), None);
+
+    let mut combos = paths!({"a", "b"} | {"c", "d", "e"});
+    assert_eq!(combos.size_hint(), (6, Some(6)));
+    assert_eq!(combos.next(), Some(Path::new("a").join("c")));
+    assert_eq!(combos.len(), 5);
+    assert_eq!(combos.last(), Some(Path::new("b").join("e")));
+}
+
+#[test]
+fn paths_evaluate_once() {
+    let calls = std::cell::Cell::new(0);
+    let fixed = || {
+        calls.set(calls.get() + 1);
+        "fixed"
+    };
+    let all: Vec<PathBuf> = paths!(fixed() | {"a", "b", "c"}).collect();
+    assert_eq!(all.len(), 3);
+    assert_eq!(calls.get(), 1);
+}
+
+#[test]
+fn match_path_patterns() {
+    fn classify(path: &Path) -> String {
+        match_path!(path, {
+            ["src", module, "mod.rs"] => format!("module {}", module.to_string_lossy()),
+            ["src", file: &str] if file.ends_with(".rs") => format!("source {}", file),
+            ["tests", .., "fixtures", _] => String::from("fixture"),
+            ["tests", rest @ ..] => format!("test {}", rest.len()),
+            [/, first: &str, ..] => format!("absolute {}", first),
+            [] => String::from("empty"),
+            _ => String::from("other"),
+        })
+    }
+    assert_eq!(classify(&path!("src" | "net" | "mod.rs")), "module net");
+    assert_eq!(classify(&path!("src" | "lib.rs")), "source lib.rs");
+    assert_eq!(classify(&path!("src" | "lib.txt")), "other");
+    assert_eq!(classify(&path!("tests" | "a" | "b" | "fixtures" | "data.json")), "fixture");
+    assert_eq!(classify(&path!("tests" | "fixtures" | "data.json")), "fixture");
+    assert_eq!(classify(&path!("tests" | "a" | "b")), "test 2");
+    assert_eq!(classify(&path!(/ "usr" | "bin")), "absolute usr");
+    assert_eq!(classify(Path::new("")), "empty");
+    assert_eq!(classify(&path!("README.md")), "other");
+}
+
+#[test]
+fn match_path_expressions() {
+    // Temporaries live for the whole match, and owned paths are only borrowed.
+    let module = match_path!(path!("src" | "net" | "mod.rs"), {
+        ["src", module: &str, "mod.rs"] => module.to_owned(),
+        _ => String::new(),
+    });
+    assert_eq!(module, "net");
+
+    let owned = PathBuf::from("a");
+    let matched = match_path!(owned, { ["a"] => true, _ => false });
+    assert!(matched);
+    assert_eq!(owned, PathBuf::from("a"));
+
+    // Control flow applies to the surrounding code.
+    let mut sources = Vec::new();
+    for path in paths!("src" | {"lib.rs", "main.rs", "README.md"}) {
+        match_path!(path, {
+            ["src", name: &str] if name.ends_with(".md") => continue,
+            ["src", name] => {
+                sources.push(name.to_owned());
+            }
+            _ => unreachable!(),
+        })
+    }
+    assert_eq!(sources, vec![OsString::from("lib.rs"), OsString::from("main.rs")]);
+}
+
+#[cfg(unix)]
+#[test]
+fn match_path_non_unicode() {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = Path::new("dir").join(OsStr::from_bytes(b"caf\xe9"));
+    let kind = match_path!(path, {
+        ["dir", _name: &str] => "unicode",
+        ["dir", name] if name.len() == 4 => "bytes",
+        _ => "other",
+    });
+    assert_eq!(kind, "bytes");
+}
@@ -0,0 +1,25 @@
+//! Checks the messages of `path!` invocations which must fail to compile.
+//!
+//! The expected output includes rustc's own wording, which changes between releases, so it is only
+//! compared on the toolchain it was written with. Update `TOOLCHAIN` when regenerating it with
+//! `TRYBUILD=overwrite`.
+
+use std::process::Command;
+
+const TOOLCHAIN: &str = "1.95.0";
+
+#[test]
+fn compile_fail() {
+    let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    let version = Command::new(rustc).arg("--version").output().expect("failed to run rustc");
+    let version = String::from_utf8_lossy(&version.stdout);
+    if version.split_whitespace().nth(1) != Some(TOOLCHAIN) {
+        eprintln!("skipping compile_fail, its output is for rustc {} and this is {}", TOOLCHAIN, version.trim());
+        return;
+    }
+
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/*.rs");
+    #[cfg(not(windows))]
+    cases.compile_fail("tests/ui/not_windows/*.rs");
+}
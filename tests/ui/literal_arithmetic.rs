@@ -0,0 +1,5 @@
+use path_dsl::path;
+
+fn main() {
+    let _ = path!("r" | "file" + 1);
+}
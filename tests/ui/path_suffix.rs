@@ -0,0 +1,6 @@
+use path_dsl::PathDSL;
+use std::path::Path;
+
+fn main() {
+    let _ = PathDSL::from("dir") + Path::new("a/b");
+}
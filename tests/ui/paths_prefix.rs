@@ -0,0 +1,5 @@
+use path_dsl::paths;
+
+fn main() {
+    for _ in paths!("dir" | "a/b_" + {0..2}) {}
+}
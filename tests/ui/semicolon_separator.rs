@@ -0,0 +1,6 @@
+use path_dsl::path;
+
+fn main() {
+    let user = "user";
+    let _ = path!("home" | user; "file");
+}
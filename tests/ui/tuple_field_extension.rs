@@ -0,0 +1,6 @@
+use path_dsl::path;
+
+fn main() {
+    let wide = (0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, "sixteen");
+    let _ = path!("r" | wide.16);
+}